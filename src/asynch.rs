@@ -0,0 +1,928 @@
+//! Async programmer implementation using tokio.
+//!
+//! This mirrors the core of the blocking [`Programmer`](crate::Programmer) API for use
+//! with async serial ports, sharing option validation, block chunking, alignment
+//! and retry handling with the blocking implementation.
+
+use core::convert::TryFrom;
+use std::io::ErrorKind as IoErrorKind;
+use std::time::Duration;
+
+use log::{trace, debug, warn, error};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{sleep, timeout};
+use tokio_serial::{DataBits, FlowControl, Parity, SerialPort as _, SerialStream, StopBits};
+
+use crate::geometry::{self, FlashGeometry};
+use crate::protocol::*;
+use crate::{check_range, check_write_alignment, Error, Options, Retry};
+
+/// AsyncSerialPort trait wrapping tokio AsyncRead / AsyncWrite with rts/dtr commands
+pub trait AsyncSerialPort: AsyncRead + AsyncWrite + Unpin {
+    fn set_rts(&mut self, level: bool) -> Result<(), IoErrorKind>;
+    fn set_dtr(&mut self, level: bool) -> Result<(), IoErrorKind>;
+}
+
+/// Async bootloader programmer
+///
+/// Reads and writes are split into blocks, range checked against the flash geometry (where set),
+/// aligned and padded, and retried as for [`Programmer`](crate::Programmer). This does not
+/// currently provide:
+///
+/// - progress reporting, resumable or streamed reads and writes
+/// - read-back verification (`write_verified`, `verify`, `compare`, `verify_reads`, `verify_erase`)
+/// - CRC, image flashing, or sector / region erase helpers (`erase_sectors`, `erase_region`)
+/// - write / readout protection, unbrick, and raw command helpers
+/// - rejection of commands not advertised by the bootloader, custom reset handlers, or transcripts
+/// - vector table checks (`sanity_check_vectors`), connection retries (`init_attempts`),
+///   or `inter_byte_delay_us` pacing
+pub struct AsyncProgrammer<P> {
+    options: Options,
+    port: P,
+    geometry: Option<FlashGeometry>,
+    version: Option<u8>,
+    read_protection: Option<bool>,
+}
+
+impl<P: AsyncSerialPort> AsyncProgrammer<P> {
+    /// Create a new async programmer instance and connect to the attached bootloader
+    /// (unless `no_connect` is set)
+    pub async fn new(port: P, options: Options) -> Result<Self, Error<IoErrorKind>> {
+        options.validate()?;
+
+        let mut s = Self { options, port, geometry: None, version: None, read_protection: None };
+
+        if !s.options.no_connect {
+            s.connect().await?;
+        }
+
+        Ok(s)
+    }
+
+    /// Destroy the programmer instance, returning the serial port
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    /// Fetch the bootloader version read on connection
+    /// (`None` if not yet connected)
+    pub fn bootloader_version(&self) -> Option<u8> {
+        self.version
+    }
+
+    /// Set the device flash geometry, used to check reads and writes are in range
+    pub fn set_geometry(&mut self, geometry: FlashGeometry) {
+        self.geometry = Some(geometry);
+    }
+
+    /// Fetch the device flash geometry (`None` if not known)
+    pub fn geometry(&self) -> Option<&FlashGeometry> {
+        self.geometry.as_ref()
+    }
+
+    /// Read the chip ID and look up the corresponding flash geometry
+    pub async fn detect_geometry(&mut self) -> Result<Option<FlashGeometry>, Error<IoErrorKind>> {
+        let id = self.chip_id().await?;
+
+        self.geometry = geometry::geometry_for(id);
+
+        match &self.geometry {
+            Some(g) => debug!("Detected {} byte flash for chip ID 0x{:04x}", g.size(), id),
+            None => warn!("Unknown flash geometry for chip ID 0x{:04x}", id),
+        }
+
+        Ok(self.geometry)
+    }
+
+    /// Reset the device into the bootloader and connect
+    pub async fn connect(&mut self) -> Result<(), Error<IoErrorKind>> {
+        // Readout protection may change across resets, so is re-checked on the next read
+        self.read_protection = None;
+
+        debug!("Resetting device");
+
+        self.reset_into_bootloader().await?;
+
+        debug!("Sending discovery character");
+
+        self.write_bytes(&[UART_DISC]).await?;
+
+        debug!("Awaiting bootloader response");
+        let v = match self.read_char().await {
+            Ok(v) => v,
+            Err(Error::Timeout) => {
+                error!("No response from bootloader");
+                return Err(Error::NoAck);
+            },
+            Err(e) => return Err(e),
+        };
+
+        match ack_from_byte(v) {
+            Ok(()) => (),
+            Err(AckError::Nack) => {
+                debug!("Received NACK to discovery, bootloader already connected");
+            },
+            Err(AckError::Invalid(v)) => {
+                error!("Unexpected response: 0x{:02x}", v);
+                return Err(Error::InvalidResponse);
+            },
+        }
+
+        sleep(Duration::from_millis(self.options.settle_delay_ms as u64)).await;
+
+        debug!("Reading bootloader info");
+        let version = self.info().await?;
+        debug!("Bootloader version: 0x{:02x}", version);
+
+        self.version = Some(version);
+
+        sleep(Duration::from_millis(self.options.settle_delay_ms as u64)).await;
+
+        Ok(())
+    }
+
+    /// Re-run the connection handshake following a device reset
+    pub async fn reconnect(&mut self) -> Result<(), Error<IoErrorKind>> {
+        debug!("Reconnecting to bootloader");
+
+        self.version = None;
+
+        self.connect().await
+    }
+
+    /// Re-synchronise with the bootloader following a desynchronised command
+    ///
+    /// See [`Programmer::resync`](crate::Programmer::resync) for details.
+    pub async fn resync(&mut self) -> Result<(), Error<IoErrorKind>> {
+        debug!("Re-synchronising with bootloader");
+
+        self.drain_rx().await?;
+
+        self.write_bytes(&[UART_DISC]).await?;
+
+        match self.read_char().await {
+            Ok(UART_NACK) => {
+                debug!("Received NACK to discovery, link recovered");
+
+                // Discard any further responses to the partial frame
+                self.drain_rx().await?;
+
+                Ok(())
+            },
+            r => {
+                debug!("No NACK to resync ({:x?}), reconnecting", r);
+                self.reconnect().await
+            },
+        }
+    }
+
+    /// Recover from a failed block read or write where retries remain (see [`Options::max_retries`]),
+    /// returning the error otherwise
+    async fn recover(&mut self, e: Error<IoErrorKind>, attempts: &mut u8) -> Result<(), Error<IoErrorKind>> {
+        match self.options.retry_action(&e, *attempts) {
+            Some(Retry::Delay) => {
+                *attempts += 1;
+
+                warn!("{}, retrying ({}/{})", e, attempts, self.options.max_retries);
+
+                sleep(Duration::from_millis(self.options.retry_delay_ms as u64)).await;
+
+                Ok(())
+            },
+            Some(Retry::Resync) => {
+                *attempts += 1;
+
+                warn!("Timeout, re-synchronising and retrying ({}/{})", attempts, self.options.max_retries);
+
+                self.resync().await
+            },
+            None => Err(e),
+        }
+    }
+
+    /// Reset the device using RTS while asserting DTR entering the bootloading or application
+    ///
+    /// If `no_reset` is set this does nothing.
+    pub async fn reset(&mut self, bootloader: bool) -> Result<(), Error<IoErrorKind>> {
+        if self.options.no_reset {
+            debug!("Skipping reset (no_reset set)");
+            return Ok(());
+        }
+
+        let (rts, dtr) = (!self.options.rts_invert, !self.options.dtr_invert);
+
+        self.port.set_rts(rts)?;
+
+        sleep(Duration::from_millis(self.options.reset_assert_ms as u64)).await;
+
+        if bootloader {
+            self.port.set_dtr(dtr)?;
+        }
+
+        self.port.set_rts(!rts)?;
+
+        sleep(Duration::from_millis(self.options.init_delay_ms as u64)).await;
+
+        if bootloader {
+            self.port.set_dtr(!dtr)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reset the device into the bootloader, equivalent to `reset(true)`
+    pub async fn reset_into_bootloader(&mut self) -> Result<(), Error<IoErrorKind>> {
+        self.reset(true).await
+    }
+
+    /// Reset the device into the application, equivalent to `reset(false)`
+    pub async fn reset_into_app(&mut self) -> Result<(), Error<IoErrorKind>> {
+        self.reset(false).await
+    }
+
+    /// Fetch bootloader version byte
+    pub async fn info(&mut self) -> Result<u8, Error<IoErrorKind>> {
+        let info = self.get().await?;
+
+        Ok(info.version)
+    }
+
+    /// Fetch bootloader version and supported commands
+    pub async fn get(&mut self) -> Result<BootloaderInfo, Error<IoErrorKind>> {
+        self.write_cmd(Command::Get).await?;
+        self.await_ack(Command::Get).await?;
+
+        let n = self.read_char().await? as usize + 1;
+
+        let version = self.read_char().await?;
+
+        let mut commands = Vec::with_capacity(n - 1);
+        for _ in 1..n {
+            let c = self.read_char().await?;
+
+            match Command::try_from(c) {
+                Ok(c) => commands.push(c),
+                Err(_) => warn!("Unrecognised command: 0x{:02x}", c),
+            }
+        }
+
+        self.await_ack(Command::Get).await?;
+
+        Ok(BootloaderInfo { version, commands })
+    }
+
+    /// Fetch device chip ID
+    pub async fn chip_id(&mut self) -> Result<u16, Error<IoErrorKind>> {
+        self.write_cmd(Command::GetId).await?;
+        self.await_ack(Command::GetId).await?;
+
+        let n = self.read_char().await? as usize + 1;
+
+        let mut v: u16 = 0;
+        for _ in 0..n {
+            let c = self.read_char().await?;
+            v = (v << 8) | c as u16;
+        }
+
+        self.await_ack(Command::GetId).await?;
+
+        Ok(v)
+    }
+
+    /// Read memory from the device
+    pub async fn read(&mut self, addr: u32, data: &mut [u8]) -> Result<(), Error<IoErrorKind>> {
+        check_range(self.geometry.as_ref(), addr, data.len())?;
+
+        let mut index = 0;
+        let total = data.len();
+
+        while index < total {
+            let a = addr + index as u32;
+            let n = self.options.read_block_len(a, total - index);
+
+            debug!("Read chunk at 0x{:08x}, length: {}", a, n);
+
+            self.read_mem_block(a, &mut data[index..index + n]).await?;
+
+            index += n;
+        }
+
+        Ok(())
+    }
+
+    async fn read_mem_block(&mut self, addr: u32, data: &mut [u8]) -> Result<(), Error<IoErrorKind>> {
+        // Check readout protection up front (once per connection), so a protected device
+        // fails immediately rather than after exhausting retries
+        if self.read_protected().await? {
+            error!("Read rejected, readout protection is active");
+            return Err(Error::ReadProtected);
+        }
+
+        let mut attempts = 0;
+
+        loop {
+            match self.try_read_mem_block(addr, data).await {
+                Err(e) => self.recover(e, &mut attempts).await?,
+                r => return r,
+            }
+        }
+    }
+
+    async fn try_read_mem_block(&mut self, addr: u32, data: &mut [u8]) -> Result<(), Error<IoErrorKind>> {
+        assert!(data.len() <= 256, "block size must be less than 256 bytes");
+
+        self.write_cmd(Command::ReadMemory).await?;
+        self.await_ack(Command::ReadMemory).await?;
+
+        self.write_addr(addr).await?;
+        self.await_ack(Command::ReadMemory).await?;
+
+        self.write_bytes(&encode_read_length(data.len())).await?;
+        self.await_ack(Command::ReadMemory).await?;
+
+        // Apply the response timeout per byte so low baud rates do not time out long blocks
+        for b in data.iter_mut() {
+            *b = self.read_char().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Check whether readout protection is active, caching the result until the next connection
+    ///
+    /// See [`Programmer::read_protected`](crate::Programmer::read_protected) for details.
+    pub async fn read_protected(&mut self) -> Result<bool, Error<IoErrorKind>> {
+        if let Some(p) = self.read_protection {
+            return Ok(p);
+        }
+
+        let p = self.probe_read_protection().await?;
+        self.read_protection = Some(p);
+
+        Ok(p)
+    }
+
+    /// Issue a ReadMemory command to check for readout protection
+    async fn probe_read_protection(&mut self) -> Result<bool, Error<IoErrorKind>> {
+        self.drain_rx().await?;
+
+        self.write_cmd(Command::ReadMemory).await?;
+
+        match self.await_ack(Command::ReadMemory).await {
+            Ok(()) => (),
+            Err(Error::Nack{ .. }) => return Ok(true),
+            Err(e) => return Err(e),
+        }
+
+        let mut frame = encode_address(0);
+        frame[4] = !frame[4];
+
+        self.write_bytes(&frame).await?;
+
+        match self.await_ack(Command::ReadMemory).await {
+            Err(Error::Nack{ .. }) => Ok(false),
+            Ok(()) => {
+                error!("Aborted read unexpectedly ACKed");
+                Err(Error::InvalidResponse)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write memory to the device
+    pub async fn write(&mut self, addr: u32, data: &[u8]) -> Result<(), Error<IoErrorKind>> {
+        check_range(self.geometry.as_ref(), addr, data.len())?;
+        check_write_alignment(&self.options, self.geometry.as_ref(), addr)?;
+
+        let mut index = 0;
+        let total = data.len();
+
+        while index < total {
+            let a = addr + index as u32;
+            let n = self.options.write_block_len(a, total - index);
+
+            self.write_block_aligned(a, &data[index..index + n], index + n == total).await?;
+
+            index += n;
+        }
+
+        Ok(())
+    }
+
+    /// Write a single block, rounding an unaligned start address down to the write alignment
+    /// and re-writing the leading bytes read back from the device (where `align_writes` is set)
+    async fn write_block_aligned(&mut self, addr: u32, chunk: &[u8], last: bool) -> Result<(), Error<IoErrorKind>> {
+        let lead = self.options.write_lead(addr);
+        if lead == 0 {
+            return self.write_block(addr, chunk, last).await;
+        }
+
+        let start = addr - lead as u32;
+
+        debug!("Aligning write at 0x{:08x} to 0x{:08x}", addr, start);
+
+        let mut buff = [0u8; MAX_CHUNK];
+        self.read_mem_block(start, &mut buff[..lead]).await?;
+        buff[lead..lead + chunk.len()].copy_from_slice(chunk);
+
+        self.write_block(start, &buff[..lead + chunk.len()], last).await
+    }
+
+    /// Write a single block, padding the final block of a write to the write alignment
+    /// and skipping blank blocks where enabled
+    async fn write_block(&mut self, addr: u32, chunk: &[u8], last: bool) -> Result<(), Error<IoErrorKind>> {
+        if self.options.skip_blank && chunk.iter().all(|b| *b == 0xFF) {
+            trace!("Skipping blank chunk at 0x{:08x}", addr);
+            return Ok(());
+        }
+
+        debug!("Write chunk at 0x{:08x}, length: {}", addr, chunk.len());
+
+        let padded = self.options.padded_len(chunk.len(), last);
+
+        if padded == chunk.len() {
+            return self.write_mem_block(addr, chunk).await;
+        }
+
+        // Pad partial words with 0xFF to match erased flash
+        debug!("Padding final block from {} to {} bytes", chunk.len(), padded);
+
+        let mut buff = [0xFFu8; MAX_CHUNK];
+        buff[..chunk.len()].copy_from_slice(chunk);
+
+        self.write_mem_block(addr, &buff[..padded]).await
+    }
+
+    async fn write_mem_block(&mut self, addr: u32, data: &[u8]) -> Result<(), Error<IoErrorKind>> {
+        let mut attempts = 0;
+
+        loop {
+            match self.try_write_mem_block(addr, data).await {
+                Err(e) => self.recover(e, &mut attempts).await?,
+                r => return r,
+            }
+        }
+    }
+
+    async fn try_write_mem_block(&mut self, addr: u32, data: &[u8]) -> Result<(), Error<IoErrorKind>> {
+        assert!(data.len() <= 256, "block size must be less than 256 bytes");
+
+        self.write_cmd(Command::WriteMemory).await?;
+        self.await_ack(Command::WriteMemory).await?;
+
+        self.write_addr(addr).await?;
+        self.await_ack(Command::WriteMemory).await?;
+
+        let buff: Vec<u8> = encode_write_data(data).collect();
+
+        self.write_bytes(&buff).await?;
+        self.await_ack(Command::WriteMemory).await
+    }
+
+    /// Erase pages by page number, returning the number of pages erased
+    pub async fn erase_pages(&mut self, pages: &[u8]) -> Result<usize, Error<IoErrorKind>> {
+        // Page count is encoded as N-1, so at least one page is required,
+        // and 256 pages (N-1 = 0xFF) would instead request a mass erase
+        let frame = match encode_erase(pages) {
+            Some(f) => f,
+            None => {
+                error!("Invalid erase page count {} (1-{} pages)", pages.len(), MAX_ERASE_PAGES);
+                return Err(Error::BufferLength);
+            },
+        };
+
+        self.write_cmd(Command::Erase).await?;
+        self.await_ack(Command::Erase).await?;
+
+        let buff: Vec<u8> = frame.collect();
+
+        self.write_bytes(&buff).await?;
+
+        self.await_ack_timeout(Command::Erase, self.options.erase_timeout_ms).await?;
+
+        Ok(pages.len())
+    }
+
+    /// Erase pages by page number using two byte addressing (v3.0+ bootloaders),
+    /// returning the number of pages erased
+    pub async fn extended_erase_pages(&mut self, pages: &[u16]) -> Result<usize, Error<IoErrorKind>> {
+        let frame = match encode_extended_erase(pages) {
+            Some(f) => f,
+            None => {
+                error!("Invalid extended erase page count {} (1-{} pages)", pages.len(), MAX_EXTENDED_ERASE_PAGES);
+                return Err(Error::BufferLength);
+            },
+        };
+
+        self.write_cmd(Command::ExtendedErase).await?;
+        self.await_ack(Command::ExtendedErase).await?;
+
+        let buff: Vec<u8> = frame.collect();
+
+        self.write_bytes(&buff).await?;
+
+        self.await_ack_timeout(Command::ExtendedErase, self.options.erase_timeout_ms).await?;
+
+        Ok(pages.len())
+    }
+
+    /// Erase the entire flash, returning the number of flash pages, or `None` if the geometry is unknown
+    pub async fn erase_all(&mut self) -> Result<Option<usize>, Error<IoErrorKind>> {
+        self.write_cmd(Command::Erase).await?;
+        self.await_ack(Command::Erase).await?;
+
+        self.write_bytes(&[0xFF, 0x00]).await?;
+        self.await_ack_timeout(Command::Erase, self.options.erase_timeout_ms).await?;
+
+        // Some F0 / L0 bootloaders send a second ACK on completion,
+        // otherwise discard any stray bytes so as not to desynchronise following commands
+        if self.options.erase_double_ack {
+            self.await_ack_timeout(Command::Erase, self.options.erase_timeout_ms).await?;
+        } else {
+            self.drain_rx().await?;
+        }
+
+        Ok(self.geometry.map(|g| g.page_count() as usize))
+    }
+
+    /// Jump to the application at the provided address
+    ///
+    /// Some bootloaders transfer control before sending the final ACK,
+    /// so a timeout awaiting this is not treated as an error.
+    pub async fn go(&mut self, addr: u32) -> Result<(), Error<IoErrorKind>> {
+        self.go_with_ack(addr, false).await
+    }
+
+    /// Jump to the application at the provided address, optionally requiring the final ACK
+    pub async fn go_with_ack(&mut self, addr: u32, expect_ack: bool) -> Result<(), Error<IoErrorKind>> {
+        self.write_cmd(Command::Go).await?;
+        self.await_ack(Command::Go).await?;
+
+        self.write_addr(addr).await?;
+
+        match self.await_ack(Command::Go).await {
+            Ok(()) => Ok(()),
+            Err(Error::Timeout) if !expect_ack => {
+                debug!("No ACK following go, assuming jump succeeded");
+                Ok(())
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write a bootloader command to the device
+    pub async fn write_cmd(&mut self, command: Command) -> Result<(), Error<IoErrorKind>> {
+        let c = encode_command(&command);
+
+        debug!("Writing command {} [0x{:02x}, 0x{:02x}]", command, c[0], c[1]);
+
+        self.write_bytes(&c).await
+    }
+
+    pub async fn write_bytes(&mut self, data: &[u8]) -> Result<(), Error<IoErrorKind>> {
+        trace!("Writing bytes: 0x{:02x?}", data);
+
+        self.port.write_all(data).await.map_err(|e| Error::Serial(e.kind()))?;
+        self.port.flush().await.map_err(|e| Error::Serial(e.kind()))?;
+
+        Ok(())
+    }
+
+    /// Write data with xor checksum
+    pub async fn write_bytes_csum(&mut self, data: &[u8]) -> Result<(), Error<IoErrorKind>> {
+        let csum = xor_checksum(data);
+
+        let mut buff = Vec::with_capacity(data.len() + 1);
+        buff.extend_from_slice(data);
+        buff.push(csum);
+
+        self.write_bytes(&buff).await
+    }
+
+    /// Write start address with xor checksum
+    async fn write_addr(&mut self, addr: u32) -> Result<(), Error<IoErrorKind>> {
+        self.write_bytes(&encode_address(addr)).await
+    }
+
+    /// Discard any pending received bytes, returning the number discarded
+    pub async fn drain_rx(&mut self) -> Result<usize, Error<IoErrorKind>> {
+        let mut n = 0;
+
+        // A zero timeout polls the read once, completing only where data is already available
+        while let Ok(r) = timeout(Duration::ZERO, self.port.read_u8()).await {
+            let v = r.map_err(|e| Error::Serial(e.kind()))?;
+            trace!("Discarding received byte: 0x{:02x}", v);
+            n += 1;
+        }
+
+        if n > 0 {
+            debug!("Discarded {} pending bytes", n);
+        }
+
+        Ok(n)
+    }
+
+    /// Read a single character from the device
+    pub async fn read_char(&mut self) -> Result<u8, Error<IoErrorKind>> {
+        self.read_char_timeout(self.options.response_timeout_ms).await
+    }
+
+    /// Read a single character from the device with the provided timeout
+    async fn read_char_timeout(&mut self, timeout_ms: u32) -> Result<u8, Error<IoErrorKind>> {
+        let t = Duration::from_millis(timeout_ms as u64);
+
+        match timeout(t, self.port.read_u8()).await {
+            Ok(Ok(v)) => Ok(v),
+            Ok(Err(e)) => Err(Error::Serial(e.kind())),
+            Err(_) => {
+                error!("Receive timeout");
+                Err(Error::Timeout)
+            },
+        }
+    }
+
+    /// Await an ack from the bootloader
+    async fn await_ack(&mut self, command: Command) -> Result<(), Error<IoErrorKind>> {
+        self.await_ack_timeout(command, self.options.response_timeout_ms).await
+    }
+
+    /// Await an ack from the bootloader with the provided timeout
+    async fn await_ack_timeout(&mut self, command: Command, timeout_ms: u32) -> Result<(), Error<IoErrorKind>> {
+        let v = self.read_char_timeout(timeout_ms).await?;
+
+        match ack_from_byte(v) {
+            Ok(()) => {
+                trace!("Received ACK!");
+                Ok(())
+            },
+            Err(AckError::Nack) => {
+                trace!("Received NACK to {}?!", command);
+                Err(Error::Nack{ command })
+            },
+            Err(AckError::Invalid(v)) => {
+                error!("Unexpected response: 0x{:02x}", v);
+                Err(Error::InvalidResponse)
+            }
+        }
+    }
+}
+
+impl AsyncSerialPort for SerialStream {
+    fn set_rts(&mut self, level: bool) -> Result<(), IoErrorKind> {
+        self.write_request_to_send(level).map_err(|e| std::io::Error::from(e).kind())
+    }
+    fn set_dtr(&mut self, level: bool) -> Result<(), IoErrorKind> {
+        self.write_data_terminal_ready(level).map_err(|e| std::io::Error::from(e).kind())
+    }
+}
+
+impl AsyncProgrammer<SerialStream> {
+    /// Create a new tokio-serial programmer instance
+    pub async fn tokio_serial(
+        port: &str,
+        baud: u32,
+        options: Options,
+    ) -> Result<Self, anyhow::Error> {
+        let (data_bits, parity, stop_bits) = serial_config(&options)?;
+
+        // Open port and apply settings
+        let builder = tokio_serial::new(port, baud)
+            .data_bits(data_bits)
+            .stop_bits(stop_bits)
+            .parity(parity)
+            .flow_control(FlowControl::None);
+
+        let port = SerialStream::open(&builder)?;
+
+        // Return instance
+        let s = Self::new(port, options).await?;
+
+        Ok(s)
+    }
+}
+
+/// Convert serial options to `tokio_serial` settings
+fn serial_config(options: &Options) -> Result<(DataBits, Parity, StopBits), anyhow::Error> {
+    options.serial_config(
+        [DataBits::Five, DataBits::Six, DataBits::Seven, DataBits::Eight],
+        [Parity::None, Parity::Odd, Parity::Even],
+        [StopBits::One, StopBits::Two],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use tokio::io::{duplex, DuplexStream, ReadBuf};
+
+    use super::*;
+
+    /// Async port over an in-memory stream, with the remote end standing in for the bootloader
+    struct TestPort(DuplexStream);
+
+    impl AsyncRead for TestPort {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.0).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for TestPort {
+        fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+            Pin::new(&mut self.0).poll_write(cx, buf)
+        }
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.0).poll_flush(cx)
+        }
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.0).poll_shutdown(cx)
+        }
+    }
+
+    impl AsyncSerialPort for TestPort {
+        fn set_rts(&mut self, _level: bool) -> Result<(), IoErrorKind> {
+            Ok(())
+        }
+        fn set_dtr(&mut self, _level: bool) -> Result<(), IoErrorKind> {
+            Ok(())
+        }
+    }
+
+    /// Run the provided test against a programmer, returning the result and the bytes written
+    ///
+    /// Each `(n, data)` response is sent by the device once `n` bytes have been written
+    fn run<F, T>(responses: &[(usize, &[u8])], f: F) -> (T, Vec<u8>)
+    where
+        F: for<'a> FnOnce(&'a mut AsyncProgrammer<TestPort>) -> Pin<Box<dyn std::future::Future<Output = T> + 'a>>,
+    {
+        run_with(Options::default(), responses, f)
+    }
+
+    /// Run the provided test against a programmer with the provided options
+    fn run_with<F, T>(options: Options, responses: &[(usize, &[u8])], f: F) -> (T, Vec<u8>)
+    where
+        F: for<'a> FnOnce(&'a mut AsyncProgrammer<TestPort>) -> Pin<Box<dyn std::future::Future<Output = T> + 'a>>,
+    {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+
+        rt.block_on(async {
+            let (local, mut remote) = duplex(1024);
+
+            let options = Options{ no_connect: true, ..options };
+            let mut p = AsyncProgrammer::new(TestPort(local), options).await.unwrap();
+
+            let responses: Vec<(usize, Vec<u8>)> = responses.iter().map(|(n, d)| (*n, d.to_vec())).collect();
+
+            let device = tokio::spawn(async move {
+                let mut written = vec![];
+                let mut pending = responses.into_iter().peekable();
+
+                loop {
+                    while let Some((_, data)) = pending.next_if(|(n, _)| *n <= written.len()) {
+                        remote.write_all(&data).await.unwrap();
+                    }
+
+                    match remote.read_u8().await {
+                        Ok(b) => written.push(b),
+                        Err(_) => break,
+                    }
+                }
+
+                written
+            });
+
+            let r = f(&mut p).await;
+            drop(p);
+
+            (r, device.await.unwrap())
+        })
+    }
+
+    #[test]
+    fn read_per_byte() {
+        let mut data = vec![UART_ACK];
+        data.extend(0..=255u8);
+
+        let (r, written) = run(&[(2, &[UART_ACK]), (7, &[UART_NACK]), (9, &[UART_ACK]), (14, &[UART_ACK]), (16, &data)], |p| Box::pin(async move {
+            let mut buff = [0u8; 256];
+            p.read(0x0800_0000, &mut buff).await.map(|_| buff)
+        }));
+
+        let expected: Vec<u8> = (0..=255u8).collect();
+        assert_eq!(r.map(|b| b.to_vec()), Ok(expected));
+        assert_eq!(written, vec![
+            0x11, 0xEE, 0x00, 0x00, 0x00, 0x00, 0xFF,
+            0x11, 0xEE, 0x08, 0x00, 0x00, 0x00, 0x08, 0xFF, 0x00,
+        ]);
+    }
+
+    #[test]
+    fn read_protection_confirmed() {
+        let (r, written) = run(&[(2, &[UART_NACK])], |p| Box::pin(async move {
+            let mut buff = [0u8; 4];
+            p.read(0x0800_0000, &mut buff).await
+        }));
+
+        // Protection is checked once up front, with no read attempted
+        assert_eq!(r, Err(Error::ReadProtected));
+        assert_eq!(written, vec![0x11, 0xEE]);
+    }
+
+    #[test]
+    fn read_nack_not_protected() {
+        let (r, written) = run(&[(2, &[UART_ACK]), (7, &[UART_NACK]), (9, &[UART_NACK])], |p| Box::pin(async move {
+            let mut buff = [0u8; 4];
+            p.read(0x0800_0000, &mut buff).await
+        }));
+
+        assert_eq!(r, Err(Error::Nack{ command: Command::ReadMemory }));
+        assert_eq!(written, vec![0x11, 0xEE, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x11, 0xEE]);
+    }
+
+    #[test]
+    fn read_nack_retried() {
+        let options = Options{ max_retries: 1, retry_delay_ms: 1, ..Default::default() };
+
+        let (r, written) = run_with(options, &[
+            (2, &[UART_ACK]), (7, &[UART_NACK]),
+            (9, &[UART_NACK]),
+            (11, &[UART_ACK]), (16, &[UART_ACK]), (18, &[UART_ACK, 0xAA, 0x55]),
+        ], |p| Box::pin(async move {
+            let mut buff = [0u8; 2];
+            p.read(0x0800_0000, &mut buff).await.map(|_| buff)
+        }));
+
+        assert_eq!(r, Ok([0xAA, 0x55]));
+        assert_eq!(&written[7..11], &[0x11, 0xEE, 0x11, 0xEE]);
+    }
+
+    #[test]
+    fn read_out_of_range() {
+        let (r, written) = run(&[], |p| Box::pin(async move {
+            p.set_geometry(geometry::geometry_for(0x0413).unwrap());
+
+            let mut buff = [0u8; 4];
+            p.read(0x080F_FFFE, &mut buff).await
+        }));
+
+        assert_eq!(r, Err(Error::OutOfRange{ addr: 0x080F_FFFE, len: 4 }));
+        assert!(written.is_empty());
+    }
+
+    #[test]
+    fn write_pads_final_block_only() {
+        let options = Options{ block_size: 8, write_alignment: 4, ..Default::default() };
+
+        let data: Vec<u8> = (0..10u8).collect();
+        let (r, written) = run_with(options, &[
+            (2, &[UART_ACK]), (7, &[UART_ACK]), (17, &[UART_ACK]),
+            (19, &[UART_ACK]), (24, &[UART_ACK]), (30, &[UART_ACK]),
+        ], |p| Box::pin(async move {
+            p.write(0x0800_0000, &data).await
+        }));
+
+        assert_eq!(r, Ok(()));
+
+        // First block is written as-is, the final two bytes are padded to the alignment
+        assert_eq!(&written[7..17], &[0x07, 0, 1, 2, 3, 4, 5, 6, 7, 0x07]);
+        assert_eq!(&written[24..30], &[0x03, 8, 9, 0xFF, 0xFF, 0x03 ^ 8 ^ 9]);
+        assert_eq!(written.len(), 30);
+    }
+
+    #[test]
+    fn erase_pages_count() {
+        let (r, written) = run(&[(2, &[UART_ACK]), (6, &[UART_ACK])], |p| Box::pin(async move {
+            p.erase_pages(&[1, 2]).await
+        }));
+
+        assert_eq!(r, Ok(2));
+        assert_eq!(written, vec![0x43, 0xBC, 0x01, 0x01, 0x02, 0x02]);
+    }
+
+    #[test]
+    fn go_expect_ack() {
+        let options = Options{ response_timeout_ms: 10, ..Default::default() };
+
+        let (r, _) = run_with(options.clone(), &[(2, &[UART_ACK])], |p| Box::pin(async move {
+            p.go(0x0800_0000).await
+        }));
+        assert_eq!(r, Ok(()));
+
+        let (r, _) = run_with(options, &[(2, &[UART_ACK])], |p| Box::pin(async move {
+            p.go_with_ack(0x0800_0000, true).await
+        }));
+        assert_eq!(r, Err(Error::Timeout));
+    }
+
+    #[test]
+    fn erase_all_drains_stray_ack() {
+        let (r, written) = run(&[(2, &[UART_ACK]), (4, &[UART_ACK, UART_ACK])], |p| Box::pin(async move {
+            let pages = p.erase_all().await?;
+            p.drain_rx().await.map(|n| (pages, n))
+        }));
+
+        assert_eq!(r, Ok((None, 0)));
+        assert_eq!(written, vec![0x43, 0xBC, 0xFF, 0x00]);
+    }
+}
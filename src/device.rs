@@ -0,0 +1,60 @@
+//! STM32 device identification.
+//!
+//! Device IDs per AN2606 / the relevant reference manuals.
+
+/// Known device IDs and product names
+pub const DEVICES: &[(u16, &str)] = &[
+    // F0
+    (0x440, "STM32F030x8/F05x"),
+    (0x442, "STM32F030xC/F09x"),
+    (0x444, "STM32F03x"),
+    (0x445, "STM32F04x/F070x6"),
+    (0x448, "STM32F070xB/F071/F072"),
+    // F1
+    (0x410, "STM32F10x Medium-density"),
+    (0x412, "STM32F10x Low-density"),
+    (0x414, "STM32F10x High-density"),
+    (0x418, "STM32F105/F107 Connectivity line"),
+    (0x420, "STM32F100 Medium-density value line"),
+    (0x428, "STM32F100 High-density value line"),
+    (0x430, "STM32F10x XL-density"),
+    // F2
+    (0x411, "STM32F2xx"),
+    // F3
+    (0x422, "STM32F302xB/C/F303xB/C/F358"),
+    (0x432, "STM32F37x"),
+    (0x438, "STM32F303x4/6/8/F334/F328"),
+    (0x439, "STM32F301/F302x4/6/8/F318"),
+    (0x446, "STM32F302xD/E/F303xD/E/F398"),
+    // F4
+    (0x413, "STM32F405/F407/F415/F417"),
+    (0x419, "STM32F42x/F43x"),
+    (0x421, "STM32F446"),
+    (0x423, "STM32F401xB/C"),
+    (0x431, "STM32F411"),
+    (0x433, "STM32F401xD/E"),
+    (0x434, "STM32F469/F479"),
+    (0x441, "STM32F412"),
+    (0x458, "STM32F410"),
+    (0x463, "STM32F413/F423"),
+    // F7
+    (0x449, "STM32F74x/F75x"),
+    (0x451, "STM32F76x/F77x"),
+    (0x452, "STM32F72x/F73x"),
+    // L0
+    (0x417, "STM32L05x/L06x"),
+    (0x425, "STM32L031/L041"),
+    (0x447, "STM32L07x/L08x"),
+    (0x457, "STM32L01x/L02x"),
+    // L4
+    (0x415, "STM32L47x/L48x"),
+    (0x435, "STM32L43x/L44x"),
+    (0x461, "STM32L496/L4A6"),
+    (0x462, "STM32L45x/L46x"),
+    (0x470, "STM32L4Rx/L4Sx"),
+];
+
+/// Fetch the product name for a device ID
+pub fn product_name(id: u16) -> Option<&'static str> {
+    DEVICES.iter().find(|(i, _)| *i == id).map(|(_, n)| *n)
+}
@@ -0,0 +1,296 @@
+//! Dry run serial port, emulating a bootloader that accepts every command
+//! so operations can be checked without attached hardware.
+//!
+//! Written data is held in a sparse memory map (with unwritten or erased memory reading
+//! as 0xFF) so verification following writes behaves as it would against a device.
+
+use core::convert::TryFrom;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::ErrorKind as IoErrorKind;
+
+use embedded_hal::serial::{Read, Write};
+use log::info;
+
+use crate::geometry::geometry_for;
+use crate::protocol::*;
+use crate::SerialPort;
+
+/// Bootloader version reported by the dry run port
+pub const DRY_RUN_VERSION: u8 = 0x31;
+
+/// Chip ID reported by the dry run port (STM32F40x / F41x)
+pub const DRY_RUN_CHIP_ID: u16 = 0x413;
+
+/// Commands reported as supported by the dry run port
+const DRY_RUN_COMMANDS: &[Command] = &[
+    Command::Get,
+    Command::GetVersionReadStatus,
+    Command::GetId,
+    Command::ReadMemory,
+    Command::Go,
+    Command::WriteMemory,
+    Command::ExtendedErase,
+    Command::WriteProtect,
+    Command::WriteUnprotect,
+    Command::ReadoutProtect,
+    Command::ReadoutUnprotect,
+];
+
+/// Expected next frame from the host
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    /// Awaiting discovery or a command
+    Idle,
+    /// Awaiting an address for the provided command
+    Address(Command),
+    /// Awaiting a read length for the provided address
+    ReadLength(u32),
+    /// Awaiting write data for the provided address
+    WriteData(u32),
+    /// Awaiting a standard erase page list
+    Erase,
+    /// Awaiting an extended erase page list
+    ExtendedErase,
+    /// Awaiting a write protect sector list
+    WriteProtect,
+}
+
+/// Dry run serial port, ACKing all commands and logging each operation
+#[derive(Clone, PartialEq, Debug)]
+pub struct DryRun {
+    /// Bytes written to the port
+    pub written: Vec<u8>,
+
+    /// Pending frame bytes
+    frame: Vec<u8>,
+
+    /// Bytes to be returned from reads
+    rx: VecDeque<u8>,
+
+    /// Written memory, with absent addresses reading as 0xFF
+    memory: BTreeMap<u32, u8>,
+
+    state: State,
+}
+
+impl Default for DryRun {
+    fn default() -> Self {
+        Self {
+            written: vec![],
+            frame: vec![],
+            rx: VecDeque::new(),
+            memory: BTreeMap::new(),
+            state: State::Idle,
+        }
+    }
+}
+
+impl DryRun {
+    /// Create a new dry run serial port
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Erase the page with the provided index using the dry run chip geometry
+    fn erase_page(&mut self, index: u16) {
+        let page = match geometry_for(DRY_RUN_CHIP_ID).and_then(|g| g.page(index)) {
+            Some(p) => p,
+            None => return,
+        };
+
+        let end = page.addr as u64 + page.size as u64;
+        self.memory.retain(|a, _| (*a as u64) < page.addr as u64 || *a as u64 >= end);
+    }
+
+    /// Handle a received byte, queuing responses once a frame is complete
+    fn receive(&mut self, b: u8) {
+        self.frame.push(b);
+
+        let f = &self.frame;
+
+        let next = match self.state {
+            State::Idle if f == &[UART_DISC] => {
+                self.rx.push_back(UART_ACK);
+                Some(State::Idle)
+            },
+            State::Idle if f.len() == 2 => match Command::try_from(f[0]) {
+                Ok(c) if f[1] == !f[0] => Some(self.command(c)),
+                _ => {
+                    info!("[dry-run] Invalid command frame: {:02x?}", f);
+                    self.rx.push_back(UART_NACK);
+                    Some(State::Idle)
+                },
+            },
+            State::Address(_) if f.len() == 5 && xor_checksum(&f[..4]) != f[4] => {
+                info!("[dry-run] Invalid address checksum: {:02x?}", f);
+                self.rx.push_back(UART_NACK);
+                Some(State::Idle)
+            },
+            State::Address(c) if f.len() == 5 => {
+                let addr = u32::from_be_bytes([f[0], f[1], f[2], f[3]]);
+                self.rx.push_back(UART_ACK);
+
+                match c {
+                    Command::ReadMemory => Some(State::ReadLength(addr)),
+                    Command::WriteMemory => Some(State::WriteData(addr)),
+                    _ => {
+                        info!("[dry-run] Go to 0x{:08x}", addr);
+                        self.rx.push_back(UART_ACK);
+                        Some(State::Idle)
+                    },
+                }
+            },
+            State::ReadLength(addr) if f.len() == 2 => {
+                let n = f[0] as usize + 1;
+                info!("[dry-run] Read {} bytes from 0x{:08x}", n, addr);
+
+                self.rx.push_back(UART_ACK);
+                for i in 0..n as u32 {
+                    let v = self.memory.get(&addr.wrapping_add(i)).copied().unwrap_or(0xFF);
+                    self.rx.push_back(v);
+                }
+                Some(State::Idle)
+            },
+            State::WriteData(addr) if f.len() == f[0] as usize + 3 => {
+                info!("[dry-run] Write {} bytes to 0x{:08x}", f.len() - 2, addr);
+
+                for (i, v) in f[1..f.len() - 1].iter().enumerate() {
+                    self.memory.insert(addr.wrapping_add(i as u32), *v);
+                }
+
+                self.rx.push_back(UART_ACK);
+                Some(State::Idle)
+            },
+            State::Erase if f.len() == 2 && f[0] == 0xFF => {
+                info!("[dry-run] Erase all pages");
+
+                self.memory.clear();
+                self.rx.push_back(UART_ACK);
+                Some(State::Idle)
+            },
+            State::Erase if f[0] != 0xFF && f.len() == f[0] as usize + 3 => {
+                info!("[dry-run] Erase pages {:?}", &f[1..f.len() - 1]);
+
+                let pages: Vec<u8> = f[1..f.len() - 1].to_vec();
+                for p in pages {
+                    self.erase_page(p as u16);
+                }
+
+                self.rx.push_back(UART_ACK);
+                Some(State::Idle)
+            },
+            State::ExtendedErase if f.len() >= 2 => {
+                let n = u16::from_be_bytes([f[0], f[1]]);
+
+                match n {
+                    0xFFF0..=0xFFFF if f.len() == 3 => {
+                        info!("[dry-run] Extended erase special 0x{:04x}", n);
+
+                        // The dry run device has a single bank, so all special erases clear the flash
+                        self.memory.clear();
+                        self.rx.push_back(UART_ACK);
+                        Some(State::Idle)
+                    },
+                    0xFFF0..=0xFFFF => None,
+                    _ if f.len() == (n as usize + 1) * 2 + 3 => {
+                        let pages: Vec<u16> = f[2..f.len() - 1]
+                            .chunks(2)
+                            .map(|p| u16::from_be_bytes([p[0], p[1]]))
+                            .collect();
+                        info!("[dry-run] Extended erase pages {:?}", pages);
+
+                        for p in pages {
+                            self.erase_page(p);
+                        }
+
+                        self.rx.push_back(UART_ACK);
+                        Some(State::Idle)
+                    },
+                    _ => None,
+                }
+            },
+            State::WriteProtect if f.len() == f[0] as usize + 3 => {
+                info!("[dry-run] Write protect sectors {:?}", &f[1..f.len() - 1]);
+
+                self.rx.push_back(UART_ACK);
+                Some(State::Idle)
+            },
+            _ => None,
+        };
+
+        if let Some(s) = next {
+            self.frame.clear();
+            self.state = s;
+        }
+    }
+
+    /// Handle a received command, returning the next state
+    fn command(&mut self, c: Command) -> State {
+        info!("[dry-run] Command {}", c);
+
+        self.rx.push_back(UART_ACK);
+
+        match c {
+            Command::Get => {
+                self.rx.push_back(DRY_RUN_COMMANDS.len() as u8);
+                self.rx.push_back(DRY_RUN_VERSION);
+                self.rx.extend(DRY_RUN_COMMANDS.iter().map(|c| *c as u8));
+                self.rx.push_back(UART_ACK);
+                State::Idle
+            },
+            Command::GetVersionReadStatus => {
+                self.rx.extend([DRY_RUN_VERSION, 0x00, 0x00, UART_ACK]);
+                State::Idle
+            },
+            Command::GetId => {
+                let id = DRY_RUN_CHIP_ID.to_be_bytes();
+                self.rx.extend([0x01, id[0], id[1], UART_ACK]);
+                State::Idle
+            },
+            Command::ReadMemory | Command::WriteMemory | Command::Go => State::Address(c),
+            Command::Erase => State::Erase,
+            Command::ExtendedErase => State::ExtendedErase,
+            Command::WriteProtect => State::WriteProtect,
+            _ => {
+                // Remaining commands send a second ACK on completion
+                self.rx.push_back(UART_ACK);
+                State::Idle
+            },
+        }
+    }
+}
+
+impl Write<u8> for DryRun {
+    type Error = IoErrorKind;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        self.written.push(word);
+        self.receive(word);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl Read<u8> for DryRun {
+    type Error = IoErrorKind;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        match self.rx.pop_front() {
+            Some(v) => Ok(v),
+            None => Err(nb::Error::WouldBlock),
+        }
+    }
+}
+
+impl SerialPort<IoErrorKind> for DryRun {
+    fn set_rts(&mut self, _level: bool) -> Result<(), IoErrorKind> {
+        Ok(())
+    }
+    fn set_dtr(&mut self, _level: bool) -> Result<(), IoErrorKind> {
+        Ok(())
+    }
+}
@@ -0,0 +1,438 @@
+//! Flash memory geometry descriptions.
+//!
+//! Describes the page / sector layout of device flash so address ranges
+//! can be translated to page indices for erasing.
+
+use crate::protocol::ExtendedEraseKind;
+
+/// Contiguous run of equally sized flash pages
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PageRun {
+    /// Number of pages in the run
+    pub count: u16,
+
+    /// Size of each page in bytes
+    pub size: u32,
+}
+
+/// Flash page
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Page {
+    /// Page index (as used by erase commands)
+    pub index: u16,
+
+    /// Page start address
+    pub addr: u32,
+
+    /// Page size in bytes
+    pub size: u32,
+}
+
+/// Flash memory bank
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Bank {
+    /// Flash bank 1 (or the only bank on single bank devices)
+    Bank1,
+
+    /// Flash bank 2
+    Bank2,
+}
+
+impl From<Bank> for ExtendedEraseKind {
+    fn from(bank: Bank) -> Self {
+        match bank {
+            Bank::Bank1 => ExtendedEraseKind::Bank1,
+            Bank::Bank2 => ExtendedEraseKind::Bank2,
+        }
+    }
+}
+
+/// Memory region kind
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MemoryKind {
+    /// Flash memory (requires erasing before writing)
+    Flash,
+
+    /// SRAM
+    Ram,
+
+    /// Other / unknown memory
+    Other,
+}
+
+/// Flash memory geometry
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FlashGeometry {
+    /// Flash base address
+    pub base: u32,
+
+    /// Page layout starting from the base address
+    pub pages: &'static [PageRun],
+
+    /// SRAM base address
+    pub ram_base: u32,
+
+    /// SRAM size in bytes
+    ///
+    /// Note that the bootloader reserves the start of SRAM for its own use, see AN2606
+    pub ram_size: u32,
+
+    /// Option byte base address
+    pub option_base: u32,
+
+    /// Option byte region size in bytes
+    pub option_size: u32,
+
+    /// 96-bit unique device ID address
+    pub uid_base: u32,
+
+    /// Index of the first page in flash bank 2 (`None` for single bank devices)
+    pub bank2_page: Option<u16>,
+}
+
+impl FlashGeometry {
+    /// Iterate over all flash pages
+    pub fn pages(&self) -> impl Iterator<Item = Page> + '_ {
+        let mut index = 0;
+        let mut addr = self.base;
+
+        self.pages
+            .iter()
+            .flat_map(|r| (0..r.count).map(move |_| r.size))
+            .map(move |size| {
+                let p = Page { index, addr, size };
+                index += 1;
+                addr += size;
+                p
+            })
+    }
+
+    /// Total flash size in bytes
+    pub fn size(&self) -> u32 {
+        self.pages.iter().map(|r| r.count as u32 * r.size).sum()
+    }
+
+    /// Total number of flash pages
+    pub fn page_count(&self) -> u16 {
+        self.pages.iter().map(|r| r.count).sum()
+    }
+
+    /// Fetch the page with the provided index
+    pub fn page(&self, index: u16) -> Option<Page> {
+        self.pages().nth(index as usize)
+    }
+
+    /// Fetch the page containing the provided address
+    pub fn page_at(&self, addr: u32) -> Option<Page> {
+        self.pages().find(|p| addr >= p.addr && addr < p.addr + p.size)
+    }
+
+    /// Iterate over pages overlapping the region `[addr, addr+len)`
+    pub fn pages_spanned(&self, addr: u32, len: usize) -> impl Iterator<Item = Page> + '_ {
+        let end = addr as u64 + len as u64;
+
+        self.pages()
+            .filter(move |p| (p.addr as u64) < end && (p.addr + p.size) > addr)
+    }
+
+    /// Fetch the flash bank containing the provided page index
+    pub fn bank_of(&self, index: u16) -> Bank {
+        match self.bank2_page {
+            Some(b) if index >= b => Bank::Bank2,
+            _ => Bank::Bank1,
+        }
+    }
+
+    /// Fetch the flash bank containing the provided address
+    pub fn bank_at(&self, addr: u32) -> Option<Bank> {
+        self.page_at(addr).map(|p| self.bank_of(p.index))
+    }
+
+    /// Iterate over the pages in the provided flash bank
+    ///
+    /// This is empty for bank 2 on single bank devices.
+    pub fn bank_pages(&self, bank: Bank) -> impl Iterator<Item = Page> + '_ {
+        self.pages().filter(move |p| self.bank_of(p.index) == bank)
+    }
+
+    /// Fetch the start address of the provided flash bank
+    pub fn bank_base(&self, bank: Bank) -> Option<u32> {
+        self.bank_pages(bank).next().map(|p| p.addr)
+    }
+
+    /// Fetch the kind of memory at the provided address
+    pub fn memory_kind(&self, addr: u32) -> MemoryKind {
+        if self.contains_flash(addr, 1) {
+            MemoryKind::Flash
+        } else if self.contains_ram(addr, 1) {
+            MemoryKind::Ram
+        } else {
+            MemoryKind::Other
+        }
+    }
+
+    /// Check whether the region `[addr, addr+len)` is entirely within flash
+    pub fn contains_flash(&self, addr: u32, len: usize) -> bool {
+        contains(self.base, self.size(), addr, len)
+    }
+
+    /// Check whether the region `[addr, addr+len)` is entirely within SRAM
+    pub fn contains_ram(&self, addr: u32, len: usize) -> bool {
+        contains(self.ram_base, self.ram_size, addr, len)
+    }
+
+    /// Check whether the region `[addr, addr+len)` starts and ends on page boundaries
+    pub fn is_aligned(&self, addr: u32, len: usize) -> bool {
+        let end = addr as u64 + len as u64;
+
+        let start_ok = self.pages().any(|p| p.addr == addr);
+        let end_ok = self.pages().any(|p| (p.addr + p.size) as u64 == end);
+
+        start_ok && end_ok
+    }
+}
+
+/// Check whether the region `[addr, addr+len)` is within `[base, base+size)`
+fn contains(base: u32, size: u32, addr: u32, len: usize) -> bool {
+    let (base, end) = (base as u64, base as u64 + size as u64);
+
+    addr as u64 >= base && addr as u64 + len as u64 <= end
+}
+
+/// Flash base address for all supported devices
+pub const FLASH_BASE: u32 = 0x0800_0000;
+
+/// SRAM base address for all supported devices
+pub const RAM_BASE: u32 = 0x2000_0000;
+
+/// System memory regions (option byte address, option byte size, unique ID address)
+type SystemRegions = (u32, u32, u32);
+
+// System memory regions by family
+const SYS_F0: SystemRegions = (0x1FFF_F800, 16, 0x1FFF_F7AC); // F0, F3
+const SYS_F1: SystemRegions = (0x1FFF_F800, 16, 0x1FFF_F7E8);
+const SYS_F4: SystemRegions = (0x1FFF_C000, 16, 0x1FFF_7A10); // F2, F4
+const SYS_F7: SystemRegions = (0x1FFF_0000, 32, 0x1FF0_F420); // F74x/75x, F76x/77x
+const SYS_F72: SystemRegions = (0x1FFF_0000, 32, 0x1FF0_7A10); // F72x/73x
+const SYS_L0: SystemRegions = (0x1FF8_0000, 32, 0x1FF8_0050);
+const SYS_L4: SystemRegions = (0x1FFF_7800, 16, 0x1FFF_7590);
+
+// F2/F4 sector layout (16K, 16K, 16K, 16K, 64K, 128K...)
+const F4_256K: &[PageRun] = &[
+    PageRun { count: 4, size: 16 * 1024 },
+    PageRun { count: 1, size: 64 * 1024 },
+    PageRun { count: 1, size: 128 * 1024 },
+];
+const F4_512K: &[PageRun] = &[
+    PageRun { count: 4, size: 16 * 1024 },
+    PageRun { count: 1, size: 64 * 1024 },
+    PageRun { count: 3, size: 128 * 1024 },
+];
+const F4_1M: &[PageRun] = &[
+    PageRun { count: 4, size: 16 * 1024 },
+    PageRun { count: 1, size: 64 * 1024 },
+    PageRun { count: 7, size: 128 * 1024 },
+];
+const F4_1M5: &[PageRun] = &[
+    PageRun { count: 4, size: 16 * 1024 },
+    PageRun { count: 1, size: 64 * 1024 },
+    PageRun { count: 11, size: 128 * 1024 },
+];
+const F4_2M: &[PageRun] = &[
+    PageRun { count: 4, size: 16 * 1024 },
+    PageRun { count: 1, size: 64 * 1024 },
+    PageRun { count: 7, size: 128 * 1024 },
+    PageRun { count: 4, size: 16 * 1024 },
+    PageRun { count: 1, size: 64 * 1024 },
+    PageRun { count: 7, size: 128 * 1024 },
+];
+
+// F7 sector layout (32K, 32K, 32K, 32K, 128K, 256K...)
+const F7_1M: &[PageRun] = &[
+    PageRun { count: 4, size: 32 * 1024 },
+    PageRun { count: 1, size: 128 * 1024 },
+    PageRun { count: 3, size: 256 * 1024 },
+];
+const F7_2M: &[PageRun] = &[
+    PageRun { count: 4, size: 32 * 1024 },
+    PageRun { count: 1, size: 128 * 1024 },
+    PageRun { count: 7, size: 256 * 1024 },
+];
+
+/// Known device flash geometries, SRAM sizes, and system memory regions by chip ID,
+/// using the largest flash size for each device
+const GEOMETRIES: &[(u16, &[PageRun], u32, SystemRegions)] = &[
+    // F0
+    (0x440, &[PageRun { count: 64, size: 1024 }], 8 * 1024, SYS_F0),
+    (0x442, &[PageRun { count: 128, size: 2048 }], 32 * 1024, SYS_F0),
+    (0x444, &[PageRun { count: 32, size: 1024 }], 4 * 1024, SYS_F0),
+    (0x445, &[PageRun { count: 32, size: 1024 }], 6 * 1024, SYS_F0),
+    (0x448, &[PageRun { count: 64, size: 2048 }], 16 * 1024, SYS_F0),
+    // F1
+    (0x410, &[PageRun { count: 128, size: 1024 }], 20 * 1024, SYS_F1),
+    (0x412, &[PageRun { count: 32, size: 1024 }], 10 * 1024, SYS_F1),
+    (0x414, &[PageRun { count: 256, size: 2048 }], 64 * 1024, SYS_F1),
+    (0x418, &[PageRun { count: 128, size: 2048 }], 64 * 1024, SYS_F1),
+    (0x420, &[PageRun { count: 128, size: 1024 }], 8 * 1024, SYS_F1),
+    (0x428, &[PageRun { count: 256, size: 2048 }], 32 * 1024, SYS_F1),
+    (0x430, &[PageRun { count: 512, size: 2048 }], 96 * 1024, SYS_F1),
+    // F2
+    (0x411, F4_1M, 128 * 1024, SYS_F4),
+    // F3
+    (0x422, &[PageRun { count: 128, size: 2048 }], 40 * 1024, SYS_F0),
+    (0x432, &[PageRun { count: 128, size: 2048 }], 32 * 1024, SYS_F0),
+    (0x438, &[PageRun { count: 32, size: 2048 }], 12 * 1024, SYS_F0),
+    (0x439, &[PageRun { count: 32, size: 2048 }], 16 * 1024, SYS_F0),
+    (0x446, &[PageRun { count: 256, size: 2048 }], 64 * 1024, SYS_F0),
+    // F4
+    (0x413, F4_1M, 128 * 1024, SYS_F4),
+    (0x419, F4_2M, 192 * 1024, SYS_F4),
+    (0x421, F4_512K, 128 * 1024, SYS_F4),
+    (0x423, F4_256K, 64 * 1024, SYS_F4),
+    (0x431, F4_512K, 128 * 1024, SYS_F4),
+    (0x433, F4_512K, 96 * 1024, SYS_F4),
+    (0x441, F4_1M, 256 * 1024, SYS_F4),
+    (0x463, F4_1M5, 320 * 1024, SYS_F4),
+    // F7
+    (0x449, F7_1M, 320 * 1024, SYS_F7),
+    (0x451, F7_2M, 512 * 1024, SYS_F7),
+    (0x452, F4_512K, 256 * 1024, SYS_F72),
+    // L0
+    (0x417, &[PageRun { count: 512, size: 128 }], 8 * 1024, SYS_L0),
+    (0x425, &[PageRun { count: 256, size: 128 }], 8 * 1024, SYS_L0),
+    (0x447, &[PageRun { count: 1536, size: 128 }], 20 * 1024, SYS_L0),
+    (0x457, &[PageRun { count: 128, size: 128 }], 2 * 1024, SYS_L0),
+    // L4
+    (0x415, &[PageRun { count: 512, size: 2048 }], 96 * 1024, SYS_L4),
+    (0x435, &[PageRun { count: 128, size: 2048 }], 48 * 1024, SYS_L4),
+    (0x461, &[PageRun { count: 512, size: 2048 }], 256 * 1024, SYS_L4),
+    (0x462, &[PageRun { count: 256, size: 2048 }], 128 * 1024, SYS_L4),
+    (0x470, &[PageRun { count: 512, size: 4096 }], 640 * 1024, SYS_L4),
+];
+
+/// Dual bank devices by chip ID, with the index of the first page in bank 2
+///
+/// Devices with user selectable bank modes (nDBANK / DBANK option bits) are
+/// listed only where the geometry above matches the dual bank layout.
+const DUAL_BANK: &[(u16, u16)] = &[
+    // F1 XL-density
+    (0x430, 256),
+    // F42x / F43x
+    (0x419, 12),
+    // L47x / L48x, L49x / L4Ax, L4Rx / L4Sx
+    (0x415, 256),
+    (0x461, 256),
+    (0x470, 256),
+];
+
+/// Fetch the flash geometry for a device by chip ID
+pub fn geometry_for(chip_id: u16) -> Option<FlashGeometry> {
+    GEOMETRIES
+        .iter()
+        .find(|(id, _, _, _)| *id == chip_id)
+        .map(|(_, pages, ram_size, (option_base, option_size, uid_base))| FlashGeometry {
+            base: FLASH_BASE,
+            pages,
+            ram_base: RAM_BASE,
+            ram_size: *ram_size,
+            option_base: *option_base,
+            option_size: *option_size,
+            uid_base: *uid_base,
+            bank2_page: DUAL_BANK.iter().find(|(id, _)| *id == chip_id).map(|(_, p)| *p),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_spanned_f4_sectors() {
+        let g = geometry_for(0x413).unwrap();
+
+        // Crossing the 16K -> 64K sector boundary
+        let p: Vec<u16> = g.pages_spanned(0x0800_BFFF, 0x4002).map(|p| p.index).collect();
+        assert_eq!(p, vec![2, 3, 4]);
+
+        // Ending exactly on a sector end does not include the following sector
+        let p: Vec<u16> = g.pages_spanned(0x0801_0000, 64 * 1024).map(|p| p.index).collect();
+        assert_eq!(p, vec![4]);
+
+        // Into the first 128K sector
+        let p: Vec<u16> = g.pages_spanned(0x0801_FFFF, 2).map(|p| p.index).collect();
+        assert_eq!(p, vec![4, 5]);
+
+        assert_eq!(g.page_at(0x0801_0000).map(|p| (p.index, p.size)), Some((4, 64 * 1024)));
+        assert_eq!(g.page_at(0x0802_0000).map(|p| (p.index, p.size)), Some((5, 128 * 1024)));
+        assert_eq!(g.size(), 1024 * 1024);
+        assert_eq!(g.page_count(), 12);
+    }
+
+    #[test]
+    fn pages_spanned_empty() {
+        let g = geometry_for(0x413).unwrap();
+
+        assert_eq!(g.pages_spanned(0x0800_0000, 0).count(), 0);
+        assert_eq!(g.pages_spanned(0x2000_0000, 16).count(), 0);
+    }
+
+    #[test]
+    fn is_aligned_sectors() {
+        let g = geometry_for(0x413).unwrap();
+
+        assert!(g.is_aligned(0x0800_0000, 16 * 1024));
+        assert!(g.is_aligned(0x0800_C000, 16 * 1024 + 64 * 1024));
+        assert!(g.is_aligned(0x0801_0000, 64 * 1024));
+
+        assert!(!g.is_aligned(0x0800_0001, 16 * 1024 - 1));
+        assert!(!g.is_aligned(0x0800_0000, 1));
+        assert!(!g.is_aligned(0x0801_0000, 16 * 1024));
+    }
+
+    #[test]
+    fn bank_helpers_dual_bank() {
+        let g = geometry_for(0x419).unwrap();
+
+        assert_eq!(g.bank_of(11), Bank::Bank1);
+        assert_eq!(g.bank_of(12), Bank::Bank2);
+
+        assert_eq!(g.bank_base(Bank::Bank1), Some(0x0800_0000));
+        assert_eq!(g.bank_base(Bank::Bank2), Some(0x0810_0000));
+
+        assert_eq!(g.bank_at(0x080F_FFFF), Some(Bank::Bank1));
+        assert_eq!(g.bank_at(0x0810_0000), Some(Bank::Bank2));
+        assert_eq!(g.bank_at(0x0820_0000), None);
+
+        assert_eq!(g.bank_pages(Bank::Bank1).count(), 12);
+        assert_eq!(g.bank_pages(Bank::Bank2).count(), 12);
+    }
+
+    #[test]
+    fn bank_helpers_single_bank() {
+        let g = geometry_for(0x413).unwrap();
+
+        assert_eq!(g.bank_of(11), Bank::Bank1);
+        assert_eq!(g.bank_at(0x0800_0000), Some(Bank::Bank1));
+        assert_eq!(g.bank_base(Bank::Bank2), None);
+        assert_eq!(g.bank_pages(Bank::Bank2).count(), 0);
+    }
+
+    #[test]
+    fn geometries_have_product_names() {
+        for (id, _, _, _) in GEOMETRIES {
+            assert!(crate::device::product_name(*id).is_some(), "no product name for chip ID 0x{:03x}", id);
+        }
+
+        for (id, _) in DUAL_BANK {
+            assert!(geometry_for(*id).is_some(), "no geometry for dual bank chip ID 0x{:03x}", id);
+        }
+
+        // Devices with a known name but no flash geometry
+        let missing: Vec<u16> = crate::device::DEVICES
+            .iter()
+            .map(|(id, _)| *id)
+            .filter(|id| geometry_for(*id).is_none())
+            .collect();
+        assert_eq!(missing, vec![0x434, 0x458]);
+    }
+}
@@ -0,0 +1,119 @@
+//! `embedded-hal` 1.0 compatibility layer.
+//!
+//! Adapts `embedded-io` serial ports, `embedded-hal` 1.0 output pins, and delays
+//! for use with the [`Programmer`](crate::Programmer).
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::serial::{Read, Write};
+use embedded_hal_1::delay::DelayNs;
+use embedded_hal_1::digital::OutputPin;
+use embedded_io::{ReadReady, WriteReady};
+
+use crate::pins::PinPortError;
+use crate::SerialPort;
+
+/// Serial port adapter over an `embedded-io` UART, with RTS / DTR driven by
+/// `embedded-hal` 1.0 output pins
+///
+/// As with [`PinControlledPort`](crate::pins::PinControlledPort) `true` drives the pin high,
+/// use the `rts_invert` and `dtr_invert` options to match the wiring.
+pub struct Hal1Port<U, RTS, DTR> {
+    uart: U,
+    rts: RTS,
+    dtr: DTR,
+}
+
+impl<U, RTS, DTR> Hal1Port<U, RTS, DTR> {
+    /// Create a new `embedded-hal` 1.0 serial port adapter
+    pub fn new(uart: U, rts: RTS, dtr: DTR) -> Self {
+        Self { uart, rts, dtr }
+    }
+
+    /// Release the underlying serial port and pins
+    pub fn free(self) -> (U, RTS, DTR) {
+        (self.uart, self.rts, self.dtr)
+    }
+}
+
+fn set_pin<P: OutputPin>(pin: &mut P, level: bool) -> Result<(), P::Error> {
+    match level {
+        true => pin.set_high(),
+        false => pin.set_low(),
+    }
+}
+
+impl<U, RTS, DTR, S, P> Read<u8> for Hal1Port<U, RTS, DTR>
+where
+    U: embedded_io::Read + embedded_io::Write + ReadReady + WriteReady + embedded_io::ErrorType<Error = S>,
+    RTS: OutputPin<Error = P>,
+    DTR: OutputPin<Error = P>,
+{
+    type Error = PinPortError<S, P>;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        // Only read when data is available to avoid blocking
+        if !self.uart.read_ready().map_err(PinPortError::Serial)? {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        let mut b = [0u8; 1];
+        match self.uart.read(&mut b).map_err(PinPortError::Serial)? {
+            1 => Ok(b[0]),
+            _ => Err(nb::Error::WouldBlock),
+        }
+    }
+}
+
+impl<U, RTS, DTR, S, P> Write<u8> for Hal1Port<U, RTS, DTR>
+where
+    U: embedded_io::Read + embedded_io::Write + ReadReady + WriteReady + embedded_io::ErrorType<Error = S>,
+    RTS: OutputPin<Error = P>,
+    DTR: OutputPin<Error = P>,
+{
+    type Error = PinPortError<S, P>;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        // Only write when space is available to avoid blocking
+        if !self.uart.write_ready().map_err(PinPortError::Serial)? {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        match self.uart.write(&[word]).map_err(PinPortError::Serial)? {
+            1 => Ok(()),
+            _ => Err(nb::Error::WouldBlock),
+        }
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.uart.flush().map_err(|e| nb::Error::Other(PinPortError::Serial(e)))
+    }
+}
+
+impl<U, RTS, DTR, S, P> SerialPort<PinPortError<S, P>> for Hal1Port<U, RTS, DTR>
+where
+    U: embedded_io::Read + embedded_io::Write + ReadReady + WriteReady + embedded_io::ErrorType<Error = S>,
+    RTS: OutputPin<Error = P>,
+    DTR: OutputPin<Error = P>,
+{
+    fn set_rts(&mut self, level: bool) -> Result<(), PinPortError<S, P>> {
+        set_pin(&mut self.rts, level).map_err(PinPortError::Pin)
+    }
+    fn set_dtr(&mut self, level: bool) -> Result<(), PinPortError<S, P>> {
+        set_pin(&mut self.dtr, level).map_err(PinPortError::Pin)
+    }
+}
+
+/// Delay adapter over an `embedded-hal` 1.0 [`DelayNs`] implementation
+pub struct Hal1Delay<D>(pub D);
+
+impl<D: DelayNs> DelayMs<u32> for Hal1Delay<D> {
+    fn delay_ms(&mut self, ms: u32) {
+        self.0.delay_ms(ms)
+    }
+}
+
+impl<D: DelayNs> DelayUs<u32> for Hal1Delay<D> {
+    fn delay_us(&mut self, us: u32) {
+        self.0.delay_us(us)
+    }
+}
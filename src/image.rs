@@ -0,0 +1,462 @@
+//! Firmware image parsing.
+//!
+//! Converts firmware files (raw binary, Intel HEX, S-record, and ELF) into
+//! non-overlapping, address sorted segments for writing to the device.
+
+use thiserror::Error;
+
+#[derive(Error, Clone, PartialEq, Debug)]
+pub enum ImageError {
+    #[error("Invalid record on line {line}")]
+    InvalidRecord { line: usize },
+    #[error("Checksum mismatch on line {line}")]
+    Checksum { line: usize },
+    #[error("Unsupported record type 0x{kind:02x} on line {line}")]
+    UnsupportedRecord { line: usize, kind: u8 },
+    #[error("Invalid ELF file: {0}")]
+    Elf(String),
+    #[error("Overlapping data at address 0x{addr:08x}")]
+    Overlap { addr: u32 },
+    #[error("Data at address 0x{addr:08x} (length {len}) exceeds the 32-bit address space")]
+    AddressOverflow { addr: u32, len: usize },
+}
+
+/// Firmware image consisting of one or more contiguous segments
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct FirmwareImage {
+    /// Address and data for each contiguous segment in the image
+    pub segments: Vec<(u32, Vec<u8>)>,
+
+    /// Entry point (if specified by the image)
+    pub entry: Option<u32>,
+}
+
+impl FirmwareImage {
+    /// Create an image from a raw binary loaded at the provided base address
+    pub fn from_bin(bytes: &[u8], base_addr: u32) -> Self {
+        FirmwareImage {
+            segments: vec![(base_addr, bytes.to_vec())],
+            entry: None,
+        }
+    }
+
+    /// Parse an Intel HEX format image
+    pub fn from_ihex(text: &str) -> Result<Self, ImageError> {
+        let mut image = FirmwareImage::default();
+        let mut base = 0u32;
+
+        for (i, l) in text.lines().enumerate() {
+            let line = i + 1;
+            let l = l.trim();
+
+            if l.is_empty() {
+                continue;
+            }
+
+            // Decode record bytes
+            let record = match l.strip_prefix(':').and_then(decode_hex) {
+                Some(r) if r.len() >= 5 => r,
+                _ => return Err(ImageError::InvalidRecord { line }),
+            };
+
+            let len = record[0] as usize;
+            if record.len() != len + 5 {
+                return Err(ImageError::InvalidRecord { line });
+            }
+
+            // Checksum is the two's complement of the sum of all other bytes
+            let sum = record.iter().fold(0u8, |a, b| a.wrapping_add(*b));
+            if sum != 0 {
+                return Err(ImageError::Checksum { line });
+            }
+
+            let addr = (record[1] as u32) << 8 | record[2] as u32;
+            let kind = record[3];
+            let data = &record[4..4 + len];
+
+            match (kind, len) {
+                // Data
+                (0x00, _) => image.push(base + addr, data)?,
+                // End of file
+                (0x01, _) => break,
+                // Extended segment address
+                (0x02, 2) => base = ((data[0] as u32) << 8 | data[1] as u32) << 4,
+                // Extended linear address
+                (0x04, 2) => base = ((data[0] as u32) << 8 | data[1] as u32) << 16,
+                // Start linear address
+                (0x05, 4) => image.entry = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]])),
+                (0x02, _) | (0x04, _) | (0x05, _) => return Err(ImageError::InvalidRecord { line }),
+                _ => return Err(ImageError::UnsupportedRecord { line, kind }),
+            }
+        }
+
+        image.normalise()?;
+
+        Ok(image)
+    }
+
+    /// Parse a Motorola S-record format image
+    pub fn from_srec(text: &str) -> Result<Self, ImageError> {
+        let mut image = FirmwareImage::default();
+
+        for (i, l) in text.lines().enumerate() {
+            let line = i + 1;
+            let l = l.trim();
+
+            if l.is_empty() {
+                continue;
+            }
+
+            // Split record type and decode record bytes
+            let (kind, record) = match (l.strip_prefix('S'), l.get(1..2), l.get(2..).and_then(decode_hex)) {
+                (Some(_), Some(k), Some(r)) if !r.is_empty() => (k, r),
+                _ => return Err(ImageError::InvalidRecord { line }),
+            };
+
+            let len = record[0] as usize;
+            if record.len() != len + 1 {
+                return Err(ImageError::InvalidRecord { line });
+            }
+
+            // Checksum is the ones' complement of the sum of all other bytes
+            let sum = record.iter().fold(0u8, |a, b| a.wrapping_add(*b));
+            if sum != 0xFF {
+                return Err(ImageError::Checksum { line });
+            }
+
+            // Address length by record type
+            let addr_len = match kind {
+                "0" | "1" | "5" | "9" => 2,
+                "2" | "6" | "8" => 3,
+                "3" | "7" => 4,
+                _ => return Err(ImageError::UnsupportedRecord { line, kind: kind.as_bytes()[0] }),
+            };
+
+            if len < addr_len + 1 {
+                return Err(ImageError::InvalidRecord { line });
+            }
+
+            let addr = record[1..1 + addr_len].iter().fold(0u32, |a, b| a << 8 | *b as u32);
+            let data = &record[1 + addr_len..len];
+
+            match kind {
+                // Data
+                "1" | "2" | "3" => image.push(addr, data)?,
+                // Termination with start address
+                "7" | "8" | "9" => {
+                    image.entry = Some(addr);
+                    break;
+                },
+                // Header and record counts
+                _ => (),
+            }
+        }
+
+        image.normalise()?;
+
+        Ok(image)
+    }
+
+    /// Parse an ELF format image, using the loadable program segments
+    #[cfg(feature = "elf")]
+    pub fn from_elf(bytes: &[u8]) -> Result<Self, ImageError> {
+        use goblin::elf::{program_header::PT_LOAD, Elf};
+
+        let elf = Elf::parse(bytes).map_err(|e| ImageError::Elf(e.to_string()))?;
+
+        let mut image = FirmwareImage {
+            entry: Some(elf.entry as u32),
+            ..Default::default()
+        };
+
+        for ph in elf.program_headers.iter().filter(|ph| ph.p_type == PT_LOAD) {
+            // Skip segments with no file data (ie. .bss)
+            if ph.p_filesz == 0 {
+                continue;
+            }
+
+            let data = bytes.get(ph.file_range())
+                .ok_or_else(|| ImageError::Elf("segment exceeds file length".to_string()))?;
+
+            image.push(ph.p_paddr as u32, data)?;
+        }
+
+        image.normalise()?;
+
+        Ok(image)
+    }
+
+    /// Total number of data bytes in the image
+    pub fn total_bytes(&self) -> usize {
+        self.segments.iter().map(|(_, d)| d.len()).sum()
+    }
+
+    /// Address range `(start, end)` covered by the image, `end` being exclusive
+    /// (and so up to `0x1_0000_0000` for an image ending at the top of the address space)
+    pub fn span(&self) -> (u32, u64) {
+        let start = self.segments.first().map(|(a, _)| *a).unwrap_or(0);
+        let end = self.segments.last().map(|(a, d)| *a as u64 + d.len() as u64).unwrap_or(start as u64);
+
+        (start, end)
+    }
+
+    /// Sort segments by address, merging contiguous segments and rejecting overlaps
+    fn normalise(&mut self) -> Result<(), ImageError> {
+        self.segments.sort_by_key(|(a, _)| *a);
+
+        let mut segments: Vec<(u32, Vec<u8>)> = Vec::with_capacity(self.segments.len());
+
+        for (addr, data) in self.segments.drain(..) {
+            if let Some((a, d)) = segments.last_mut() {
+                let end = *a as u64 + d.len() as u64;
+
+                if (addr as u64) < end {
+                    return Err(ImageError::Overlap { addr });
+                } else if addr as u64 == end {
+                    d.extend_from_slice(&data);
+                    continue;
+                }
+            }
+
+            segments.push((addr, data));
+        }
+
+        self.segments = segments;
+
+        Ok(())
+    }
+
+    /// Append data at the provided address, extending the last segment if contiguous
+    fn push(&mut self, addr: u32, data: &[u8]) -> Result<(), ImageError> {
+        if addr as u64 + data.len() as u64 > 1 << 32 {
+            return Err(ImageError::AddressOverflow { addr, len: data.len() });
+        }
+
+        if let Some((a, d)) = self.segments.last_mut() {
+            if *a as u64 + d.len() as u64 == addr as u64 {
+                d.extend_from_slice(data);
+                return Ok(());
+            }
+        }
+
+        self.segments.push((addr, data.to_vec()));
+
+        Ok(())
+    }
+}
+
+/// Decode a string of hex characters into bytes
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode an Intel HEX record with a valid checksum
+    fn ihex(addr: u16, kind: u8, data: &[u8]) -> String {
+        let mut r = vec![data.len() as u8, (addr >> 8) as u8, addr as u8, kind];
+        r.extend_from_slice(data);
+        r.push(r.iter().fold(0u8, |a, b| a.wrapping_add(*b)).wrapping_neg());
+
+        r.iter().fold(":".to_string(), |s, b| s + &format!("{:02X}", b))
+    }
+
+    /// Encode an S-record with a valid checksum
+    fn srec(kind: char, addr: &[u8], data: &[u8]) -> String {
+        let mut r = vec![(addr.len() + data.len() + 1) as u8];
+        r.extend_from_slice(addr);
+        r.extend_from_slice(data);
+        r.push(!r.iter().fold(0u8, |a, b| a.wrapping_add(*b)));
+
+        r.iter().fold(format!("S{}", kind), |s, b| s + &format!("{:02X}", b))
+    }
+
+    #[test]
+    fn ihex_records() {
+        let text = [
+            ihex(0x0000, 0x04, &[0x08, 0x00]),
+            ihex(0x0000, 0x00, &[0x01, 0x02, 0x03, 0x04]),
+            ihex(0x0004, 0x00, &[0x05, 0x06]),
+            ihex(0x0000, 0x05, &[0x08, 0x00, 0x01, 0x23]),
+            ihex(0x0000, 0x01, &[]),
+            // Records following end of file are ignored
+            ihex(0x0100, 0x00, &[0xAA]),
+        ].join("\n");
+
+        let image = FirmwareImage::from_ihex(&text).unwrap();
+
+        assert_eq!(image.segments, vec![(0x0800_0000, vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06])]);
+        assert_eq!(image.entry, Some(0x0800_0123));
+        assert_eq!(image.span(), (0x0800_0000, 0x0800_0006));
+    }
+
+    #[test]
+    fn ihex_checksum() {
+        let mut r = ihex(0x0000, 0x00, &[0x01, 0x02]);
+        r.replace_range(r.len() - 2.., "00");
+
+        let text = [ihex(0x0000, 0x04, &[0x08, 0x00]), r].join("\n");
+
+        assert_eq!(FirmwareImage::from_ihex(&text), Err(ImageError::Checksum { line: 2 }));
+    }
+
+    #[test]
+    fn ihex_segments() {
+        // Out of order contiguous records are merged, with gaps producing separate segments
+        let text = [
+            ihex(0x0010, 0x00, &[0x03, 0x04]),
+            ihex(0x0000, 0x00, &[0x01]),
+            ihex(0x000E, 0x00, &[0x02, 0x02]),
+            ihex(0x0000, 0x01, &[]),
+        ].join("\n");
+
+        let image = FirmwareImage::from_ihex(&text).unwrap();
+
+        assert_eq!(image.segments, vec![
+            (0x0000, vec![0x01]),
+            (0x000E, vec![0x02, 0x02, 0x03, 0x04]),
+        ]);
+        assert_eq!(image.total_bytes(), 5);
+    }
+
+    #[test]
+    fn ihex_overlap() {
+        let text = [
+            ihex(0x0000, 0x00, &[0x01, 0x02, 0x03, 0x04]),
+            ihex(0x0002, 0x00, &[0x05]),
+        ].join("\n");
+
+        assert_eq!(FirmwareImage::from_ihex(&text), Err(ImageError::Overlap { addr: 0x0002 }));
+    }
+
+    #[test]
+    fn ihex_address_overflow() {
+        // Data ending exactly at the top of the address space is accepted
+        let text = [
+            ihex(0x0000, 0x04, &[0xFF, 0xFF]),
+            ihex(0xFFF0, 0x00, &[0xAA; 16]),
+        ].join("\n");
+
+        let image = FirmwareImage::from_ihex(&text).unwrap();
+        assert_eq!(image.span(), (0xFFFF_FFF0, 0x1_0000_0000));
+
+        let text = [
+            ihex(0x0000, 0x04, &[0xFF, 0xFF]),
+            ihex(0xFFF0, 0x00, &[0xAA; 17]),
+        ].join("\n");
+
+        assert_eq!(FirmwareImage::from_ihex(&text), Err(ImageError::AddressOverflow { addr: 0xFFFF_FFF0, len: 17 }));
+    }
+
+    #[test]
+    fn srec_address_widths() {
+        let text = [
+            srec('0', &[0x00, 0x00], b"hdr"),
+            srec('1', &[0x10, 0x00], &[0x01, 0x02]),
+            srec('2', &[0x02, 0x00, 0x00], &[0x03]),
+            srec('3', &[0x08, 0x00, 0x00, 0x00], &[0x04, 0x05]),
+            srec('3', &[0x08, 0x00, 0x00, 0x02], &[0x06]),
+            srec('5', &[0x00, 0x05], &[]),
+            srec('7', &[0x08, 0x00, 0x01, 0x23], &[]),
+        ].join("\n");
+
+        let image = FirmwareImage::from_srec(&text).unwrap();
+
+        // Header (S0) and count (S5) records contribute no data
+        assert_eq!(image.segments, vec![
+            (0x0000_1000, vec![0x01, 0x02]),
+            (0x0002_0000, vec![0x03]),
+            (0x0800_0000, vec![0x04, 0x05, 0x06]),
+        ]);
+        assert_eq!(image.entry, Some(0x0800_0123));
+    }
+
+    #[test]
+    fn srec_entry_widths() {
+        let text = [srec('1', &[0x00, 0x00], &[0x01]), srec('9', &[0x12, 0x34], &[])].join("\n");
+        assert_eq!(FirmwareImage::from_srec(&text).unwrap().entry, Some(0x1234));
+
+        let text = [srec('2', &[0x00, 0x00, 0x00], &[0x01]), srec('8', &[0x12, 0x34, 0x56], &[])].join("\n");
+        assert_eq!(FirmwareImage::from_srec(&text).unwrap().entry, Some(0x12_3456));
+
+        // Records following termination are ignored
+        let text = [srec('9', &[0x00, 0x10], &[]), srec('1', &[0x00, 0x00], &[0x01])].join("\n");
+        let image = FirmwareImage::from_srec(&text).unwrap();
+        assert_eq!(image.entry, Some(0x10));
+        assert!(image.segments.is_empty());
+    }
+
+    #[test]
+    fn srec_checksum() {
+        // A two's complement checksum (as for IHEX) must be rejected
+        let mut r = srec('1', &[0x00, 0x00], &[0x01]);
+        let sum = u8::from_str_radix(&r[r.len() - 2..], 16).unwrap();
+        r.replace_range(r.len() - 2.., &format!("{:02X}", sum.wrapping_add(1)));
+
+        let text = [srec('0', &[0x00, 0x00], &[]), r].join("\n");
+
+        assert_eq!(FirmwareImage::from_srec(&text), Err(ImageError::Checksum { line: 2 }));
+    }
+
+    /// Build a minimal 32-bit little endian ARM ELF with the provided `(p_paddr, p_vaddr, data, p_memsz)` PT_LOAD segments
+    #[cfg(feature = "elf")]
+    fn elf(entry: u32, segments: &[(u32, u32, &[u8], u32)]) -> Vec<u8> {
+        let phoff = 52u32;
+        let mut offset = phoff + 32 * segments.len() as u32;
+
+        let mut b = vec![0x7F, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        b.extend(2u16.to_le_bytes());           // e_type (EXEC)
+        b.extend(40u16.to_le_bytes());          // e_machine (ARM)
+        b.extend(1u32.to_le_bytes());           // e_version
+        b.extend(entry.to_le_bytes());          // e_entry
+        b.extend(phoff.to_le_bytes());          // e_phoff
+        b.extend(0u32.to_le_bytes());           // e_shoff
+        b.extend(0u32.to_le_bytes());           // e_flags
+        b.extend(52u16.to_le_bytes());          // e_ehsize
+        b.extend(32u16.to_le_bytes());          // e_phentsize
+        b.extend((segments.len() as u16).to_le_bytes());
+        b.extend(40u16.to_le_bytes());          // e_shentsize
+        b.extend(0u16.to_le_bytes());           // e_shnum
+        b.extend(0u16.to_le_bytes());           // e_shstrndx
+
+        for (paddr, vaddr, data, memsz) in segments {
+            for v in [1, offset, *vaddr, *paddr, data.len() as u32, *memsz, 6, 4] {
+                b.extend(v.to_le_bytes());
+            }
+            offset += data.len() as u32;
+        }
+
+        for (_, _, data, _) in segments {
+            b.extend_from_slice(data);
+        }
+
+        b
+    }
+
+    #[cfg(feature = "elf")]
+    #[test]
+    fn elf_segments() {
+        let bytes = elf(0x0800_0101, &[
+            // .text loaded at the physical address
+            (0x0800_0000, 0x0800_0000, &[0x01, 0x02, 0x03, 0x04], 4),
+            // .data with a RAM virtual address, loaded from flash
+            (0x0800_0004, 0x2000_0000, &[0x05, 0x06], 2),
+            // .bss with no file data
+            (0x2000_0002, 0x2000_0002, &[], 0x100),
+        ]);
+
+        let image = FirmwareImage::from_elf(&bytes).unwrap();
+
+        assert_eq!(image.segments, vec![(0x0800_0000, vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06])]);
+        assert_eq!(image.entry, Some(0x0800_0101));
+    }
+}
@@ -1,16 +1,22 @@
 //! STM32 Serial Bootloader.
 //!
 //! Base on AN3155
+//!
+//! This crate is `no_std` compatible with the default `std` feature disabled,
+//! in which case allocating helpers (such as [`Programmer::get`] and flash region
+//! erasing) are not available.
+
+#![cfg_attr(not(feature = "std"), no_std)]
 
+use core::convert::TryFrom;
 use core::fmt::Debug;
 use core::marker::PhantomData;
 
-use log::{trace, debug, info, error};
+use log::{trace, debug, info, warn, error};
 
-use nb::block;
 use thiserror::Error;
 
-use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
 use embedded_hal::serial::{Read, Write};
 
 #[cfg(feature = "linux")]
@@ -18,9 +24,90 @@ extern crate linux_embedded_hal;
 #[cfg(feature = "linux")]
 pub mod linux;
 
+#[cfg(feature = "serialport")]
+pub mod serialport;
+
+#[cfg(feature = "async")]
+pub mod asynch;
+
+#[cfg(all(feature = "std", any(test, feature = "mock")))]
+pub mod mock;
+
+#[cfg(feature = "std")]
+pub mod dry_run;
+
+#[cfg(feature = "std")]
+mod transcript;
+
+pub mod pins;
+
+#[cfg(feature = "hal1")]
+pub mod hal1;
+
 pub mod protocol;
 use protocol::*;
 
+#[cfg(feature = "std")]
+pub mod image;
+
+pub mod device;
+
+pub mod geometry;
+use geometry::{Bank, FlashGeometry, MemoryKind};
+
+#[cfg(feature = "std")]
+use image::FirmwareImage;
+
+
+/// Number of immediate read attempts before backing off to `poll_delay_ms`
+const POLL_SPIN_ATTEMPTS: u32 = 16;
+
+/// Chunk size for reading back data during verification
+pub const VERIFY_CHUNK: usize = 128;
+
+/// Maximum number of mismatched offsets reported by a memory compare
+pub const MAX_COMPARE_MISMATCHES: usize = 4096;
+
+/// Minimum number of connection attempts following readout unprotect when unbricking
+pub const UNBRICK_ATTEMPTS: u8 = 5;
+
+/// Elapsed time tracking for poll timeouts
+///
+/// This accumulates the requested poll delays, and with `std` also measures the real
+/// elapsed time using a monotonic clock, expiring on whichever is greater (so delay and
+/// syscall overheads do not extend the timeout, while delays that do not sleep still expire).
+struct PollTimer {
+    delayed_ms: u32,
+    #[cfg(feature = "std")]
+    start: std::time::Instant,
+}
+
+impl PollTimer {
+    fn new() -> Self {
+        Self {
+            delayed_ms: 0,
+            #[cfg(feature = "std")]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    /// Restart the timer
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Record a poll delay, returning whether the provided timeout has elapsed
+    fn expired(&mut self, delay_ms: u32, timeout_ms: u32) -> bool {
+        self.delayed_ms = self.delayed_ms.saturating_add(delay_ms);
+
+        #[cfg(feature = "std")]
+        let elapsed = u32::max(self.delayed_ms, self.start.elapsed().as_millis() as u32);
+        #[cfg(not(feature = "std"))]
+        let elapsed = self.delayed_ms;
+
+        elapsed > timeout_ms
+    }
+}
 
 /// SerialPort trait wrapping embedded-hal with rts/dtr commands
 pub trait SerialPort<E>: Write<u8, Error = E> + Read<u8, Error = E> {
@@ -28,12 +115,45 @@ pub trait SerialPort<E>: Write<u8, Error = E> + Read<u8, Error = E> {
     fn set_dtr(&mut self, level: bool) -> Result<(), E>;
 }
 
+/// Progress sink for long running operations
+pub trait Progress {
+    /// Called following each completed block with the bytes transferred and the total transfer length
+    fn on_chunk(&mut self, done: usize, total: usize);
+}
+
+impl<F: FnMut(usize, usize)> Progress for F {
+    fn on_chunk(&mut self, done: usize, total: usize) {
+        self(done, total)
+    }
+}
+
+/// No-op progress sink
+impl Progress for () {
+    fn on_chunk(&mut self, _done: usize, _total: usize) {}
+}
+
+/// Reset request passed to custom reset handlers
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResetRequest {
+    /// Reset the device into the bootloader
+    Bootloader,
+
+    /// Reset the device into the application
+    Application,
+}
+
+/// Custom reset handler, for boards where reset and boot mode are not driven by RTS / DTR
+#[cfg(feature = "std")]
+pub type ResetHandler<E> = Box<dyn FnMut(ResetRequest) -> Result<(), E>>;
+
 #[derive(Error, Clone, PartialEq, Debug)]
 pub enum Error<SerialError: Debug> {
     #[error("Serial device error: {0:?}")]
     Serial(SerialError),
-    #[error("Nack")]
-    Nack,
+    #[error("Nack in response to {command} command")]
+    Nack{ command: Command },
+    #[error("Nack in response to raw command")]
+    RawNack,
     #[error("NoAck")]
     NoAck,
     #[error("Timeout")]
@@ -42,6 +162,29 @@ pub enum Error<SerialError: Debug> {
     InvalidResponse,
     #[error("BufferLength")]
     BufferLength,
+    #[error("Verify mismatch at address 0x{addr:08x}")]
+    VerifyMismatch{ addr: u32 },
+    #[error("CRC mismatch (expected 0x{expected:08x}, actual 0x{actual:08x})")]
+    CrcMismatch{ expected: u32, actual: u32 },
+    #[error("Readout protection is active")]
+    ReadProtected,
+    #[error("Flash geometry unknown")]
+    UnknownGeometry,
+    #[error("Region at 0x{addr:08x} (length {len}) is not page aligned")]
+    Unaligned{ addr: u32, len: usize },
+    #[error("Region at 0x{addr:08x} (length {len}) is out of range")]
+    OutOfRange{ addr: u32, len: usize },
+    #[error("Write address 0x{addr:08x} is not aligned to {align} bytes")]
+    UnalignedWrite{ addr: u32, align: usize },
+    #[error("{command} command not supported by bootloader v{}.{}", .version >> 4, .version & 0x0F)]
+    Unsupported{ command: Command, version: u8 },
+    #[error("Flash {bank:?} not present on device")]
+    InvalidBank{ bank: Bank },
+    #[error("Page {page} not erased")]
+    EraseFailed{ page: u16 },
+    #[error("Sector {sector} is not present in flash geometry")]
+    InvalidSector{ sector: u8 },
+    #[cfg(feature = "std")]
     #[error("Io error: {0:?}")]
     Io(std::io::ErrorKind),
 }
@@ -52,380 +195,2086 @@ impl<SerialError: Debug> From<SerialError> for Error<SerialError> {
     }
 }
 
+/// Serial port parity
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+impl core::str::FromStr for Parity {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Parity::None),
+            "odd" => Ok(Parity::Odd),
+            "even" => Ok(Parity::Even),
+            _ => Err("parity must be one of 'none', 'odd', or 'even'"),
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 #[cfg_attr(feature = "structopt", derive(structopt::StructOpt))]
 pub struct Options {
-    /// Do not reset the device on connection
+    /// Do not reset the device (or toggle RTS / DTR), for devices already strapped into
+    /// the bootloader. The discovery character is still sent on connection.
     #[cfg_attr(feature = "structopt", structopt(long))]
     pub no_reset: bool,
 
+    /// Invert RTS (reset) polarity, asserting reset with RTS low
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub rts_invert: bool,
+
+    /// Invert DTR (boot mode) polarity, selecting the bootloader with DTR low
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub dtr_invert: bool,
+
+    /// Do not connect to the bootloader in [`Programmer::new`]
+    #[cfg_attr(feature = "structopt", structopt(skip))]
+    pub no_connect: bool,
+
+    /// Serial data bits (5 to 8)
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "8"))]
+    pub data_bits: u8,
+
+    /// Serial parity (none, odd, or even)
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "even"))]
+    pub parity: Parity,
+
+    /// Serial stop bits (1 or 2)
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "1"))]
+    pub stop_bits: u8,
+
     /// Timeout to wait for bootloader responses
     #[cfg_attr(feature = "structopt", structopt(long, default_value = "100"))]
     pub response_timeout_ms: u32,
 
+    /// Timeout to wait for erase (and other long running) operations to complete
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "30000"))]
+    pub erase_timeout_ms: u32,
+
     /// Period to poll for bootloader responses
     #[cfg_attr(feature = "structopt", structopt(long, default_value = "10"))]
     pub poll_delay_ms: u32,
 
+    /// Period to hold the device in reset
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "10"))]
+    pub reset_assert_ms: u32,
+
     /// Period to wait for bootloader init before sending init character
     #[cfg_attr(feature = "structopt", structopt(long, default_value = "100"))]
     pub init_delay_ms: u32,
 
+    /// Period to wait for the bootloader to settle following the discovery response and version read
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "100"))]
+    pub settle_delay_ms: u32,
+
+    /// Number of reset and discovery attempts on connection, waiting `retry_delay_ms` between attempts
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "1"))]
+    pub init_attempts: u8,
+
+    /// Delay between transmitted bytes to pace writes over slow or isolated links
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "0"))]
+    pub inter_byte_delay_us: u32,
+
     /// Disable progress bars during operations
     #[cfg_attr(feature = "structopt", structopt(long))]
     pub no_progress: bool,
+
+    /// Erase entire pages when flashing regions that are not page aligned
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub erase_unaligned: bool,
+
+    /// Block size for memory reads and writes (maximum 256 bytes, and a multiple of `write_alignment`)
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "256"))]
+    pub block_size: usize,
+
+    /// Alignment (in bytes) required for write start addresses, with the end of writes padded to this with 0xFF
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "4"))]
+    pub write_alignment: usize,
+
+    /// Round unaligned write start addresses down to `write_alignment`, reading back and re-writing
+    /// the leading bytes (otherwise unaligned writes to flash are rejected)
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub align_writes: bool,
+
+    /// Skip writing blocks that are entirely 0xFF (requires the region to be pre-erased)
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub skip_blank: bool,
+
+    /// Number of times to retry a block read or write on NACK (or read mismatch with `verify_reads`)
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "0"))]
+    pub max_retries: u8,
+
+    /// Period to wait before retrying a block read or write
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "100"))]
+    pub retry_delay_ms: u32,
+
+    /// Await a second completion ACK following a mass erase (as sent by some F0 / L0 bootloaders)
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub erase_double_ack: bool,
+
+    /// Read each block twice, failing (or retrying) where the reads disagree
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub verify_reads: bool,
+
+    /// Read back the first word of each page following erase to confirm the erase has occurred
+    ///
+    /// This requires the device flash geometry
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub verify_erase: bool,
+
+    /// Attempt to re-synchronise with the bootloader (see [`Programmer::resync`]) prior to
+    /// retrying a block read or write following a timeout
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub resync_on_timeout: bool,
+
+    /// Warn where data written to the start of flash does not begin with a plausible
+    /// vector table (initial SP in SRAM, reset vector within the written region)
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub sanity_check_vectors: bool,
 }
 
 impl Default for Options {
     fn default() -> Self {
         Self {
             no_reset: false,
+            rts_invert: false,
+            dtr_invert: false,
+            no_connect: false,
+            data_bits: 8,
+            parity: Parity::Even,
+            stop_bits: 1,
             no_progress: false,
+            erase_unaligned: false,
             response_timeout_ms: 100,
+            erase_timeout_ms: 30_000,
             poll_delay_ms: 10,
+            reset_assert_ms: 10,
             init_delay_ms: 100,
+            settle_delay_ms: 100,
+            init_attempts: 1,
+            inter_byte_delay_us: 0,
+            block_size: MAX_CHUNK,
+            write_alignment: 4,
+            align_writes: false,
+            skip_blank: false,
+            max_retries: 0,
+            retry_delay_ms: 100,
+            erase_double_ack: false,
+            verify_reads: false,
+            verify_erase: false,
+            resync_on_timeout: false,
+            sanity_check_vectors: false,
         }
     }
 }
 
-pub struct Programmer<P, D, E> {
-    options: Options,
-    port: P,
-    delay: D,
-    _err: PhantomData<E>,
-}
+impl Options {
+    /// Create an [`OptionsBuilder`] starting from the default options
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::default()
+    }
 
-impl<P, D, E> Programmer<P, D, E>
-where
-    P: SerialPort<E>,
-    D: DelayMs<u32>,
-    E: core::fmt::Debug,
-{
-    /// Create a new programmer instance and connect to the attached bootloader
-    pub fn new(port: P, delay: D, options: Options) -> Result<Self, Error<E>> {
-        let mut s = Self {
-            options,
-            port,
-            delay,
-            _err: PhantomData,
-        };
+    /// Check the options are consistent, the block size must be between 1 and [`MAX_CHUNK`] bytes
+    /// and a multiple of the write alignment, so only the final block of a write requires padding
+    pub fn validate<E: core::fmt::Debug>(&self) -> Result<(), Error<E>> {
+        if self.block_size == 0 || self.block_size > MAX_CHUNK {
+            error!("Block size {} must be between 1 and {} bytes", self.block_size, MAX_CHUNK);
+            return Err(Error::BufferLength);
+        }
 
-        s.init()?;
+        if !self.block_size.is_multiple_of(self.write_alignment.max(1)) {
+            error!("Block size {} is not a multiple of the write alignment {}", self.block_size, self.write_alignment);
+            return Err(Error::BufferLength);
+        }
 
-        Ok(s)
+        Ok(())
     }
 
-    // Initialise the programmer/bootloader
-    fn init(&mut self) -> Result<(), Error<E>> {
-        // First, reset device
-        debug!("Resetting device");
-
-        self.reset(true)?;
+    /// Length of the next read block at `addr`, splitting reads at block boundaries
+    /// so an unaligned start only shortens the first block
+    pub(crate) fn read_block_len(&self, addr: u32, remaining: usize) -> usize {
+        let block = self.block_size;
 
-        debug!("Sending discovery character");
+        usize::min(remaining, block - addr as usize % block)
+    }
 
-        // Then, send discovery character
-        block!(self.port.write(UART_DISC)).unwrap();
-        block!(self.port.flush()).unwrap();
+    /// Number of bytes preceding the provided address in its write alignment word
+    /// (where `align_writes` is set)
+    pub(crate) fn write_lead(&self, addr: u32) -> usize {
+        if !self.align_writes {
+            return 0;
+        }
 
-        // Wait for a response
-        debug!("Awaiting bootloader response");
-        let _ = self.await_ack();
+        addr as usize % self.write_alignment.max(1)
+    }
 
-        // Wait for bootloader to think a little
-        self.delay.delay_ms(100);
+    /// Length of the next write block at `addr`, leaving space for any leading bytes
+    /// re-written to align the block start
+    pub(crate) fn write_block_len(&self, addr: u32, remaining: usize) -> usize {
+        usize::min(remaining, self.block_size - self.write_lead(addr))
+    }
 
-        // Read info
-        debug!("Reading bootloader info");
-        let version = self.info()?;
-        debug!("Bootloader version: 0x{:02x}", version);
+    /// Length to write for a block, padding only the final block of a write to the write alignment
+    pub(crate) fn padded_len(&self, len: usize, last: bool) -> usize {
+        let align = self.write_alignment.max(1);
 
-        self.delay.delay_ms(100);
+        if !last || len.is_multiple_of(align) {
+            return len;
+        }
 
-        // Return ok
-        Ok(())
+        usize::min(len.div_ceil(align) * align, MAX_CHUNK)
     }
 
-    /// Fetch bootloader info byte
-    // TODO: there's more useful info than just this?
-    pub fn info(&mut self) -> Result<u8, Error<E>> {
-        let mut data = [0u8; 12];
+    /// Recovery action before retrying a block read or write following the provided error,
+    /// or `None` where the error should be returned
+    pub(crate) fn retry_action<E: Debug>(&self, e: &Error<E>, attempts: u8) -> Option<Retry> {
+        if attempts >= self.max_retries {
+            return None;
+        }
 
-        // Write command
-        self.write_cmd(Command::Get)?;
+        match e {
+            Error::Nack{ .. } | Error::InvalidResponse => Some(Retry::Delay),
+            Error::Timeout if self.resync_on_timeout => Some(Retry::Resync),
+            _ => None,
+        }
+    }
+}
 
-        // Await ack
-        self.await_ack()?;
+/// Recovery action prior to retrying a failed block read or write
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Retry {
+    /// Wait for `retry_delay_ms`
+    Delay,
+    /// Re-synchronise with the bootloader
+    Resync,
+}
 
-        // Read comand length
-        let n = self.read_char()? as usize + 1;
+/// Check the region `[addr, addr+len)` is within device flash or SRAM
+/// (where the flash geometry is known)
+pub(crate) fn check_range<E: Debug>(geometry: Option<&FlashGeometry>, addr: u32, len: usize) -> Result<(), Error<E>> {
+    match geometry {
+        Some(g) if !g.contains_flash(addr, len) && !g.contains_ram(addr, len) => {
+            error!("Region 0x{:08x} (length {}) is not within flash or SRAM", addr, len);
+            Err(Error::OutOfRange{ addr, len })
+        },
+        _ => Ok(()),
+    }
+}
 
-        debug!("Reading {} bytes", n);
+/// Check a write start address meets the write alignment, unless `align_writes` is set
+/// or the address is known to be in SRAM
+pub(crate) fn check_write_alignment<E: Debug>(options: &Options, geometry: Option<&FlashGeometry>, addr: u32) -> Result<(), Error<E>> {
+    let align = options.write_alignment.max(1);
+    let ram = geometry.map(|g| g.memory_kind(addr) == MemoryKind::Ram).unwrap_or(false);
 
-        if data.len() < n {
-            error!("RX buffer too short");
-            return Err(Error::BufferLength);
-        }
+    if !(addr as usize).is_multiple_of(align) && !options.align_writes && !ram {
+        error!("Write address 0x{:08x} is not aligned to {} bytes (see `align_writes`)", addr, align);
+        return Err(Error::UnalignedWrite{ addr, align });
+    }
 
-        // Read data
-        for i in 0..n {
-            data[i] = self.read_char()?;
-        }
+    Ok(())
+}
 
-        // Await final ack
-        self.await_ack()?;
+#[cfg(any(feature = "linux", feature = "serialport", feature = "async"))]
+impl Options {
+    /// Map the serial line options to a backend's settings types, using the provided
+    /// tables of 5-8 data bits, none / odd / even parity, and 1-2 stop bits
+    pub(crate) fn serial_config<D: Copy, P: Copy, S: Copy>(
+        &self,
+        data_bits: [D; 4],
+        parity: [P; 3],
+        stop_bits: [S; 2],
+    ) -> Result<(D, P, S), anyhow::Error> {
+        let d = match self.data_bits {
+            5..=8 => data_bits[self.data_bits as usize - 5],
+            v => return Err(anyhow::anyhow!("Unsupported data bits: {}", v)),
+        };
 
-        debug!("Received: 0x{:02x?}", &data[..n]);
+        let s = match self.stop_bits {
+            1..=2 => stop_bits[self.stop_bits as usize - 1],
+            v => return Err(anyhow::anyhow!("Unsupported stop bits: {}", v)),
+        };
 
-        Ok(data[0])
-    }
+        let p = match self.parity {
+            Parity::None => parity[0],
+            Parity::Odd => parity[1],
+            Parity::Even => parity[2],
+        };
 
-    /// Erase pages by page offset and count
-    pub fn erase(&mut self, page_offset: u8, page_count: u8) -> Result<(), Error<E>> {
+        Ok((d, p, s))
+    }
+}
 
-        debug!("Erasing {} pages from index {}", page_count, page_offset);
-        let pages: Vec<u8> = (page_count..page_offset+page_count).collect();
+/// Builder for [`Options`], starting from [`Options::default`]
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct OptionsBuilder {
+    options: Options,
+}
 
-        self.erase_pages(&pages)
+impl OptionsBuilder {
+    /// Do not reset the device (or toggle RTS / DTR)
+    pub fn no_reset(mut self, no_reset: bool) -> Self {
+        self.options.no_reset = no_reset;
+        self
     }
 
-    /// Erase pages by page number
-    pub fn erase_pages(&mut self, pages: &[u8]) -> Result<(), Error<E>> {
-        // Write command
-        self.write_cmd(Command::Erase)?;
-        self.await_ack()?;
+    /// Invert RTS (reset) polarity
+    pub fn rts_invert(mut self, rts_invert: bool) -> Self {
+        self.options.rts_invert = rts_invert;
+        self
+    }
 
-        // Write number of pages
-        let len = (pages.len() - 1) as u8;
-        block!(self.port.write(len))?;
+    /// Invert DTR (boot mode) polarity
+    pub fn dtr_invert(mut self, dtr_invert: bool) -> Self {
+        self.options.dtr_invert = dtr_invert;
+        self
+    }
 
-        // Write page list
-        self.write_bytes_csum(pages)?;
+    /// Do not connect to the bootloader in [`Programmer::new`]
+    pub fn no_connect(mut self, no_connect: bool) -> Self {
+        self.options.no_connect = no_connect;
+        self
+    }
 
-        self.await_ack()
+    /// Serial data bits (5 to 8)
+    pub fn data_bits(mut self, data_bits: u8) -> Self {
+        self.options.data_bits = data_bits;
+        self
     }
 
-    /// Erase the entire flash
-    pub fn erase_all(&mut self) -> Result<(), Error<E>> {
-        // Write command
-        self.write_cmd(Command::Erase)?;
-        self.await_ack()?;
+    /// Serial parity
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.options.parity = parity;
+        self
+    }
 
-        self.write_bytes(&[0xFF, 0x00])?;
-        self.await_ack()?;
+    /// Serial stop bits (1 or 2)
+    pub fn stop_bits(mut self, stop_bits: u8) -> Self {
+        self.options.stop_bits = stop_bits;
+        self
+    }
 
-        Ok(())
+    /// Timeout to wait for bootloader responses
+    pub fn response_timeout_ms(mut self, response_timeout_ms: u32) -> Self {
+        self.options.response_timeout_ms = response_timeout_ms;
+        self
     }
 
-    /// Read memory from the device
-    pub fn read(&mut self, addr: u32, data: &mut [u8]) -> Result<(), Error<E>> {
-        let mut index = 0;
-        
-        // Setup progress bar _if_ enabled
-        #[cfg(feature="indicatif")]
-        let mut p = match !self.options.no_progress {
-            true => {
-                let pb = indicatif::ProgressBar::new(data.len() as u64);
+    /// Timeout to wait for erase (and other long running) operations to complete
+    pub fn erase_timeout_ms(mut self, erase_timeout_ms: u32) -> Self {
+        self.options.erase_timeout_ms = erase_timeout_ms;
+        self
+    }
 
-                pb.set_style(indicatif::ProgressStyle::default_bar()
-                    .template("{spinner:.green} [{elapsed_precise}] [{bar:80.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-                    .progress_chars("#>-"));
+    /// Period to poll for bootloader responses
+    pub fn poll_delay_ms(mut self, poll_delay_ms: u32) -> Self {
+        self.options.poll_delay_ms = poll_delay_ms;
+        self
+    }
 
-                Some(pb)
-            },
-            _ => None,
-        };
+    /// Period to hold the device in reset
+    pub fn reset_assert_ms(mut self, reset_assert_ms: u32) -> Self {
+        self.options.reset_assert_ms = reset_assert_ms;
+        self
+    }
 
-        for chunk in data.chunks_mut(MAX_CHUNK as usize) {
-            debug!("Read chunk at 0x{:08x}, length: {}", addr + index as u32, chunk.len());
+    /// Period to wait for bootloader init before sending init character
+    pub fn init_delay_ms(mut self, init_delay_ms: u32) -> Self {
+        self.options.init_delay_ms = init_delay_ms;
+        self
+    }
 
-            self.read_mem_block(addr + index as u32, &mut chunk[..])?;
+    /// Period to wait for the bootloader to settle following discovery and version read
+    pub fn settle_delay_ms(mut self, settle_delay_ms: u32) -> Self {
+        self.options.settle_delay_ms = settle_delay_ms;
+        self
+    }
 
-            index += chunk.len();
+    /// Number of reset and discovery attempts on connection
+    pub fn init_attempts(mut self, init_attempts: u8) -> Self {
+        self.options.init_attempts = init_attempts;
+        self
+    }
 
-            // Update progress bar (if enabled)
-            #[cfg(feature="indicatif")]
-            if let Some(p) = &mut p {
-                p.inc(chunk.len() as u64)
-            }
-        }
+    /// Delay between transmitted bytes to pace writes over slow or isolated links
+    pub fn inter_byte_delay_us(mut self, inter_byte_delay_us: u32) -> Self {
+        self.options.inter_byte_delay_us = inter_byte_delay_us;
+        self
+    }
 
-        Ok(())
+    /// Disable progress bars during operations
+    pub fn no_progress(mut self, no_progress: bool) -> Self {
+        self.options.no_progress = no_progress;
+        self
     }
 
-    fn read_mem_block(&mut self, addr: u32, data: &mut [u8]) -> Result<(), Error<E>> {
-        assert!(data.len() <= 256, "block size must be less than 256 bytes");
+    /// Erase entire pages when flashing regions that are not page aligned
+    pub fn erase_unaligned(mut self, erase_unaligned: bool) -> Self {
+        self.options.erase_unaligned = erase_unaligned;
+        self
+    }
 
-        // Write read command and await ack
-        self.write_cmd(Command::ReadMemory)?;
-        self.await_ack()?;
-        
+    /// Block size for memory reads and writes (maximum 256 bytes, and a multiple of `write_alignment`)
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.options.block_size = block_size;
+        self
+    }
 
-        // Write start address + xor checksum and await ack
-        let addr = [(addr >> 24) as u8, (addr >> 16) as u8, (addr >> 8) as u8, addr as u8];
-        let addr_csum = addr[0] ^ addr[1] ^ addr[2] ^ addr[3];
+    /// Alignment (in bytes) to pad the end of writes to with 0xFF
+    pub fn write_alignment(mut self, write_alignment: usize) -> Self {
+        self.options.write_alignment = write_alignment;
+        self
+    }
 
-        for a in &addr {
-            block!(self.port.write(*a))?;
-        }
-        block!(self.port.write(addr_csum))?;
-        block!(self.port.flush())?;
+    /// Round unaligned write start addresses down to the write alignment, re-writing the leading bytes
+    pub fn align_writes(mut self, align_writes: bool) -> Self {
+        self.options.align_writes = align_writes;
+        self
+    }
 
-        self.await_ack()?;
+    /// Skip writing blocks that are entirely 0xFF
+    pub fn skip_blank(mut self, skip_blank: bool) -> Self {
+        self.options.skip_blank = skip_blank;
+        self
+    }
 
+    /// Number of times to retry a block read or write on NACK
+    pub fn max_retries(mut self, max_retries: u8) -> Self {
+        self.options.max_retries = max_retries;
+        self
+    }
 
-        // Write read length and checksum and await ack
-        let len = (data.len() - 1) as u8;
-        self.write_bytes(&[len, !len])?;
+    /// Period to wait before retrying a block read or write
+    pub fn retry_delay_ms(mut self, retry_delay_ms: u32) -> Self {
+        self.options.retry_delay_ms = retry_delay_ms;
+        self
+    }
 
-        self.await_ack()?;
+    /// Await a second completion ACK following a mass erase
+    pub fn erase_double_ack(mut self, erase_double_ack: bool) -> Self {
+        self.options.erase_double_ack = erase_double_ack;
+        self
+    }
 
-        // Read response data
-        for i in 0..data.len() {
-            data[i] = self.read_char()?;
-        }
+    /// Read each block twice, failing (or retrying) where the reads disagree
+    pub fn verify_reads(mut self, verify_reads: bool) -> Self {
+        self.options.verify_reads = verify_reads;
+        self
+    }
 
-        Ok(())
+    /// Read back the first word of each page following erase to confirm the erase has occurred
+    pub fn verify_erase(mut self, verify_erase: bool) -> Self {
+        self.options.verify_erase = verify_erase;
+        self
     }
 
-    /// Write memory to the device
-    pub fn write(&mut self, addr: u32, data: &[u8]) -> Result<(), Error<E>> {
-        let mut index = 0;
+    /// Re-synchronise with the bootloader before retrying a block read or write following a timeout
+    pub fn resync_on_timeout(mut self, resync_on_timeout: bool) -> Self {
+        self.options.resync_on_timeout = resync_on_timeout;
+        self
+    }
 
-        // Setup progress bar _if_ enabled
-        #[cfg(feature="indicatif")]
-        let mut p = match !self.options.no_progress {
-            true => {
-                let pb = indicatif::ProgressBar::new(data.len() as u64);
+    /// Warn where data written to the start of flash does not begin with a plausible vector table
+    pub fn sanity_check_vectors(mut self, sanity_check_vectors: bool) -> Self {
+        self.options.sanity_check_vectors = sanity_check_vectors;
+        self
+    }
 
-                pb.set_style(indicatif::ProgressStyle::default_bar()
-                    .template("{spinner:.green} [{elapsed_precise}] [{bar:80.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-                    .progress_chars("#>-"));
+    /// Build the configured [`Options`], returning [`Error::BufferLength`] where the
+    /// block size is invalid (see [`Options::validate`])
+    pub fn build<E: core::fmt::Debug>(self) -> Result<Options, Error<E>> {
+        self.options.validate()?;
 
-                Some(pb)
-            },
-            _ => None,
-        };
+        Ok(self.options)
+    }
+}
 
-        for chunk in data.chunks(MAX_CHUNK as usize) {
-            debug!("Write chunk at 0x{:08x}, length: {}", addr + index as u32, chunk.len());
+pub struct Programmer<P, D, E> {
+    options: Options,
+    port: P,
+    delay: D,
+    geometry: Option<FlashGeometry>,
+    version: Option<u8>,
+    commands: Option<CommandSet>,
+    read_protection: Option<bool>,
+    #[cfg(feature = "std")]
+    reset_handler: Option<ResetHandler<E>>,
+    #[cfg(feature = "std")]
+    transcript: transcript::Transcript,
+    _err: PhantomData<E>,
+}
 
-            self.write_mem_block(addr + index as u32, &chunk[..])?;
+impl<P, D, E> Programmer<P, D, E>
+where
+    P: SerialPort<E>,
+    D: DelayMs<u32> + DelayUs<u32>,
+    E: core::fmt::Debug,
+{
+    /// Create a new programmer instance and connect to the attached bootloader
+    /// (unless `no_connect` is set)
+    pub fn new(port: P, delay: D, options: Options) -> Result<Self, Error<E>> {
+        options.validate()?;
 
-            index += chunk.len();
+        let mut s = Self::create(port, delay, options);
 
-            // Update progress bar (if enabled)
-            #[cfg(feature="indicatif")]
-            if let Some(p) = &mut p {
-                p.inc(chunk.len() as u64)
-            }
+        if !s.options.no_connect {
+            s.connect()?;
         }
 
-        Ok(())
+        Ok(s)
     }
 
-    fn write_mem_block(&mut self, addr: u32, data: &[u8]) -> Result<(), Error<E>> {
-        assert!(data.len() <= 256, "block size must be less than 256 bytes");
-
-        // Write read command and await ack
-        self.write_cmd(Command::WriteMemory)?;
-        self.await_ack()?;
-        
+    /// Create a new programmer instance using a custom reset handler in place of RTS / DTR,
+    /// and connect to the attached bootloader (unless `no_connect` is set)
+    #[cfg(feature = "std")]
+    pub fn with_reset(port: P, delay: D, options: Options, reset_handler: ResetHandler<E>) -> Result<Self, Error<E>> {
+        options.validate()?;
 
-        // Write start address + xor checksum and await ack
-        let addr = [(addr >> 24) as u8, (addr >> 16) as u8, (addr >> 8) as u8, addr as u8];
-        let addr_csum = addr[0] ^ addr[1] ^ addr[2] ^ addr[3];
+        let mut s = Self::create(port, delay, options);
+        s.reset_handler = Some(reset_handler);
 
-        for a in &addr {
-            block!(self.port.write(*a))?;
+        if !s.options.no_connect {
+            s.connect()?;
         }
-        block!(self.port.write(addr_csum))?;
-        block!(self.port.flush())?;
-
-        self.await_ack()?;
 
+        Ok(s)
+    }
 
-        // Set write length and checksum and await ack
-        let len = (data.len() - 1) as u8;
-        let mut data_csum = len;
-
-        block!(self.port.write(len))?;
-        for d in data {
-            data_csum ^= *d;
-            block!(self.port.write(*d))?;
+    fn create(port: P, delay: D, options: Options) -> Self {
+        Self {
+            options,
+            port,
+            delay,
+            geometry: None,
+            version: None,
+            commands: None,
+            read_protection: None,
+            #[cfg(feature = "std")]
+            reset_handler: None,
+            #[cfg(feature = "std")]
+            transcript: Default::default(),
+            _err: PhantomData,
         }
-        block!(self.port.write(data_csum))?;
+    }
 
-        self.await_ack()?;
+    /// Destroy the programmer instance, returning the serial port and delay
+    pub fn into_parts(self) -> (P, D) {
+        (self.port, self.delay)
+    }
 
+    /// Check whether the bootloader supports the provided command
+    /// (`None` if the supported commands are not yet known)
+    pub fn supports(&self, command: Command) -> Option<bool> {
+        self.commands.map(|c| c.contains(command))
+    }
+
+    /// Fetch the bootloader version read on connection
+    /// (`None` if not yet connected)
+    pub fn bootloader_version(&self) -> Option<u8> {
+        self.version
+    }
+
+    /// Set the flash geometry of the attached device
+    pub fn set_geometry(&mut self, geometry: FlashGeometry) {
+        self.geometry = Some(geometry);
+    }
+
+    /// Fetch the flash geometry of the attached device (if known)
+    pub fn geometry(&self) -> Option<&FlashGeometry> {
+        self.geometry.as_ref()
+    }
+
+    /// Detect the flash geometry of the attached device using the chip ID
+    pub fn detect_geometry(&mut self) -> Result<Option<FlashGeometry>, Error<E>> {
+        let id = self.chip_id()?;
+
+        self.geometry = geometry::geometry_for(id);
+
+        match &self.geometry {
+            Some(g) => debug!("Detected {} byte flash for chip ID 0x{:04x}", g.size(), id),
+            None => warn!("Unknown flash geometry for chip ID 0x{:04x}", id),
+        }
+
+        Ok(self.geometry)
+    }
+
+    /// Reset the device into the bootloader and connect
+    ///
+    /// This is called by [`Programmer::new`], see [`Programmer::reconnect`] for
+    /// re-connecting following commands that cause a device reset
+    pub fn connect(&mut self) -> Result<(), Error<E>> {
+        // Readout protection may change across resets, so is re-checked on the next read
+        self.read_protection = None;
+
+        // Reset and send discovery character, retrying up to `init_attempts` times
+        let attempts = self.options.init_attempts.max(1);
+
+        for i in 1..=attempts {
+            match self.discover() {
+                Ok(()) => break,
+                Err(e @ Error::NoAck) | Err(e @ Error::InvalidResponse) if i < attempts => {
+                    warn!("Connection attempt {}/{} failed ({}), retrying", i, attempts, e);
+
+                    self.delay.delay_ms(self.options.retry_delay_ms);
+                },
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Wait for bootloader to think a little
+        self.delay.delay_ms(self.options.settle_delay_ms);
+
+        // Read info
+        debug!("Reading bootloader info");
+        let version = self.info()?;
+        debug!("Bootloader version: 0x{:02x}", version);
+
+        self.delay.delay_ms(self.options.settle_delay_ms);
+
+        // Return ok
+        Ok(())
+    }
+
+    /// Reset the device and perform the discovery handshake
+    fn discover(&mut self) -> Result<(), Error<E>> {
+        // First, reset device
+        debug!("Resetting device");
+
+        self.reset_into_bootloader()?;
+
+        // Discard any bytes received during reset
+        self.drain_rx()?;
+
+        debug!("Sending discovery character");
+
+        // Then, send discovery character
+        self.write_byte(UART_DISC)?;
+        self.flush()?;
+
+        // Wait for a response, skipping a single spurious leading zero
+        debug!("Awaiting bootloader response");
+        let v = match self.read_char() {
+            Ok(0x00) => {
+                debug!("Skipping leading 0x00 in discovery response");
+                self.read_char()
+            },
+            r => r,
+        };
+
+        let v = match v {
+            Ok(v) => v,
+            Err(Error::Timeout) => {
+                error!("No response from bootloader");
+                return Err(Error::NoAck);
+            },
+            Err(e) => return Err(e),
+        };
+
+        #[cfg(feature = "std")]
+        self.transcript.complete("Discovery");
+
+        match ack_from_byte(v) {
+            Ok(()) => (),
+            Err(AckError::Nack) => {
+                debug!("Received NACK to discovery, bootloader already connected");
+            },
+            Err(AckError::Invalid(v)) => {
+                error!("Unexpected response: 0x{:02x}", v);
+                return Err(Error::InvalidResponse);
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Send the discovery character and await a single response, without resetting the device
+    /// or reading bootloader info
+    ///
+    /// Returns `true` if a bootloader ACKs discovery (or NACKs it, when already connected),
+    /// or `false` on timeout or any other response. This waits at most one response timeout.
+    pub fn handshake(&mut self) -> Result<bool, Error<E>> {
+        self.drain_rx()?;
+
+        self.write_byte(UART_DISC)?;
+        self.flush()?;
+
+        match self.read_char() {
+            Ok(UART_ACK) | Ok(UART_NACK) => Ok(true),
+            Ok(v) => {
+                debug!("Unexpected discovery response: 0x{:02x}", v);
+                Ok(false)
+            },
+            Err(Error::Timeout) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Re-run the connection handshake following a device reset
+    ///
+    /// This must be called after commands that cause a device reset
+    /// (such as [`Programmer::write_unprotect`]) before issuing further commands,
+    /// and re-reads the bootloader version.
+    pub fn reconnect(&mut self) -> Result<(), Error<E>> {
+        debug!("Reconnecting to bootloader");
+
+        self.version = None;
+        self.commands = None;
+
+        self.connect()
+    }
+
+    /// Re-synchronise with the bootloader following a desynchronised command
+    ///
+    /// This discards pending received bytes and sends the discovery character, which
+    /// a connected bootloader will NACK (either directly or in place of the frame it
+    /// was awaiting). If no NACK is received a full [`Programmer::reconnect`] is performed.
+    pub fn resync(&mut self) -> Result<(), Error<E>> {
+        debug!("Re-synchronising with bootloader");
+
+        self.drain_rx()?;
+
+        self.write_byte(UART_DISC)?;
+        self.flush()?;
+
+        match self.read_char() {
+            Ok(UART_NACK) => {
+                debug!("Received NACK to discovery, link recovered");
+
+                // Discard any further responses to the partial frame
+                self.drain_rx()?;
+
+                Ok(())
+            },
+            r => {
+                debug!("No NACK to resync ({:x?}), reconnecting", r);
+                self.reconnect()
+            },
+        }
+    }
+
+    /// Fetch bootloader version byte
+    pub fn info(&mut self) -> Result<u8, Error<E>> {
+        self.get_with(|_| ())
+    }
+
+    /// Fetch bootloader version and supported commands
+    #[cfg(feature = "std")]
+    pub fn get(&mut self) -> Result<BootloaderInfo, Error<E>> {
+        let mut commands = Vec::new();
+
+        let version = self.get_with(|c| commands.push(c))?;
+
+        debug!("Received version: 0x{:02x} commands: {:?}", version, commands);
+
+        Ok(BootloaderInfo{ version, commands })
+    }
+
+    /// Execute the Get command, calling `f` for each supported command
+    /// and returning the bootloader version
+    ///
+    /// This caches the supported commands, with subsequent commands not
+    /// advertised by the bootloader rejected with [`Error::Unsupported`].
+    fn get_with<F: FnMut(Command)>(&mut self, mut f: F) -> Result<u8, Error<E>> {
+        // Write command
+        self.write_cmd(Command::Get)?;
+
+        // Await ack
+        self.await_ack(Command::Get)?;
+
+        // Read response length
+        let n = self.read_char()? as usize + 1;
+
+        debug!("Reading {} bytes", n);
+
+        // Read version
+        let version = self.read_char()?;
+
+        // Read supported commands
+        let mut commands = CommandSet::default();
+
+        for _ in 1..n {
+            let c = self.read_char()?;
+
+            match Command::try_from(c) {
+                Ok(c) => {
+                    trace!("Supported command: {}", c);
+                    commands.insert(c);
+                    f(c)
+                },
+                Err(_) => warn!("Unrecognised command: 0x{:02x}", c),
+            }
+        }
+
+        // Await final ack
+        self.await_ack(Command::Get)?;
+
+        self.version = Some(version);
+        self.commands = Some(commands);
+
+        Ok(version)
+    }
+
+    /// Fetch bootloader version and read protection option bytes
+    ///
+    /// Returns (version, option1, option2)
+    pub fn version_read_status(&mut self) -> Result<(u8, u8, u8), Error<E>> {
+        // Write command
+        self.write_cmd(Command::GetVersionReadStatus)?;
+
+        // Await ack
+        self.await_ack(Command::GetVersionReadStatus)?;
+
+        // Read fixed length response
+        let version = self.read_char()?;
+        let option1 = self.read_char()?;
+        let option2 = self.read_char()?;
+
+        // Await final ack
+        self.await_ack(Command::GetVersionReadStatus)?;
+
+        debug!("Received version: 0x{:02x} options: 0x{:02x} 0x{:02x}", version, option1, option2);
+
+        Ok((version, option1, option2))
+    }
+
+    /// Erase pages by page offset and count, returning the number of pages erased
+    pub fn erase(&mut self, page_offset: u8, page_count: u8) -> Result<usize, Error<E>> {
+        debug!("Erasing {} pages from index {}", page_count, page_offset);
+
+        // Pages must not extend beyond single byte addressing
+        if page_offset as usize + page_count as usize > 256 {
+            error!("Erase of {} pages from index {} exceeds page 255", page_count, page_offset);
+            return Err(Error::BufferLength);
+        }
+
+        let mut buff = [0u8; u8::MAX as usize];
+        let pages = &mut buff[..page_count as usize];
+        for (i, p) in pages.iter_mut().enumerate() {
+            *p = page_offset + i as u8;
+        }
+
+        self.erase_pages(pages)
+    }
+
+    /// Erase pages by page number, returning the number of pages erased
+    pub fn erase_pages(&mut self, pages: &[u8]) -> Result<usize, Error<E>> {
+        // Page count is encoded as N-1, so at least one page is required,
+        // and 256 pages (N-1 = 0xFF) would instead request a mass erase
+        let frame = match encode_erase(pages) {
+            Some(f) => f,
+            None => {
+                error!("Invalid erase page count {} (1-{} pages)", pages.len(), MAX_ERASE_PAGES);
+                return Err(Error::BufferLength);
+            },
+        };
+
+        // Write command
+        self.write_cmd(Command::Erase)?;
+        self.await_ack(Command::Erase)?;
+
+        // Write number of pages and page list, checksum covers both
+        for d in frame {
+            self.write_byte(d)?;
+        }
+        self.flush()?;
+
+        self.await_cmd_ack_timeout(Command::Erase, self.options.erase_timeout_ms)?;
+
+        if self.options.verify_erase {
+            self.verify_erased(pages.iter().map(|p| *p as u16))?;
+        }
+
+        Ok(pages.len())
+    }
+
+    /// Erase pages by page number using two byte addressing (v3.0+ bootloaders),
+    /// returning the number of pages erased
+    pub fn extended_erase_pages(&mut self, pages: &[u16]) -> Result<usize, Error<E>> {
+        let frame = match encode_extended_erase(pages) {
+            Some(f) => f,
+            None => {
+                error!("Invalid extended erase page count {} (1-{} pages)", pages.len(), MAX_EXTENDED_ERASE_PAGES);
+                return Err(Error::BufferLength);
+            },
+        };
+
+        // Write command
+        self.write_cmd(Command::ExtendedErase)?;
+        self.await_ack(Command::ExtendedErase)?;
+
+        // Write number of pages and page list, checksum covers both
+        for d in frame {
+            self.write_byte(d)?;
+        }
+        self.flush()?;
+
+        self.await_cmd_ack_timeout(Command::ExtendedErase, self.options.erase_timeout_ms)?;
+
+        if self.options.verify_erase {
+            self.verify_erased(pages.iter().copied())?;
+        }
+
+        Ok(pages.len())
+    }
+
+    /// Perform a special (mass or bank) extended erase (v3.0+ bootloaders)
+    pub fn extended_erase(&mut self, kind: ExtendedEraseKind) -> Result<(), Error<E>> {
+        // Write command
+        self.write_cmd(Command::ExtendedErase)?;
+        self.await_ack(Command::ExtendedErase)?;
+
+        // Write special erase code with checksum
+        let code = kind.code();
+        self.write_bytes_csum(&[(code >> 8) as u8, code as u8])?;
+
+        // Mass and bank erases can take some time to complete
+        self.await_cmd_ack_timeout(Command::ExtendedErase, self.options.erase_timeout_ms)
+    }
+
+    /// Erase a flash bank on dual bank devices (v3.0+ bootloaders)
+    ///
+    /// Where the device flash geometry is known this checks the bank exists,
+    /// and verifies the bank is blank when `verify_erase` is set.
+    /// Returns the number of pages in the bank, or `None` if the geometry is unknown.
+    pub fn erase_bank(&mut self, bank: Bank) -> Result<Option<usize>, Error<E>> {
+        let geometry = self.geometry;
+
+        if let Some(g) = &geometry {
+            if g.bank_base(bank).is_none() {
+                error!("Flash {:?} not present on device", bank);
+                return Err(Error::InvalidBank{ bank });
+            }
+        }
+
+        self.extended_erase(bank.into())?;
+
+        if let (true, Some(g)) = (self.options.verify_erase, &geometry) {
+            self.verify_erased(g.bank_pages(bank).map(|p| p.index))?;
+        }
+
+        Ok(geometry.map(|g| g.bank_pages(bank).count()))
+    }
+
+    /// Erase pages by page number, using extended erase where supported by the bootloader
+    #[cfg(feature = "std")]
+    fn erase_page_list(&mut self, pages: &[u16]) -> Result<usize, Error<E>> {
+        let extended = match self.supports(Command::ExtendedErase) {
+            Some(v) => v,
+            None => self.get()?.commands.contains(&Command::ExtendedErase),
+        };
+
+        if extended {
+            self.extended_erase_pages(pages)
+        } else if pages.len() <= MAX_ERASE_PAGES && pages.iter().all(|p| *p <= 255) {
+            let pages: Vec<u8> = pages.iter().map(|p| *p as u8).collect();
+            self.erase_pages(&pages)
+        } else {
+            error!("Pages exceed standard erase addressing");
+            Err(Error::BufferLength)
+        }
+    }
+
+    /// Erase flash sectors by sector number (as described in the F2/F4/F7 reference manuals)
+    ///
+    /// Sectors correspond to the `FlashGeometry` pages, and are erased using
+    /// extended erase where supported by the bootloader. This requires the device flash geometry.
+    /// Returns the number of sectors erased.
+    #[cfg(feature = "std")]
+    pub fn erase_sectors(&mut self, sectors: &[u8]) -> Result<usize, Error<E>> {
+        let geometry = self.geometry.ok_or(Error::UnknownGeometry)?;
+
+        if let Some(s) = sectors.iter().find(|s| **s as u16 >= geometry.page_count()) {
+            error!("Sector {} exceeds device sector count ({})", s, geometry.page_count());
+            return Err(Error::InvalidSector{ sector: *s });
+        }
+
+        for p in geometry.pages().filter(|p| sectors.contains(&(p.index as u8))) {
+            debug!("Erasing sector {} (0x{:08x}, {} bytes)", p.index, p.addr, p.size);
+        }
+
+        let pages: Vec<u16> = sectors.iter().map(|s| *s as u16).collect();
+
+        self.erase_page_list(&pages)
+    }
+
+    /// Erase all pages overlapping the region `[addr, addr+len)`, returning the number of pages erased
+    ///
+    /// This requires the device flash geometry
+    #[cfg(feature = "std")]
+    pub fn erase_region(&mut self, addr: u32, len: usize) -> Result<usize, Error<E>> {
+        let geometry = self.geometry.ok_or(Error::UnknownGeometry)?;
+
+        let pages: Vec<u16> = geometry.pages_spanned(addr, len).map(|p| p.index).collect();
+
+        debug!("Erasing pages: {:?}", pages);
+
+        if pages.is_empty() {
+            return Ok(0);
+        }
+
+        self.erase_page_list(&pages)
+    }
+
+    /// Erase the entire flash
+    ///
+    /// Some F0 / L0 bootloaders send a second ACK on completion of the mass erase,
+    /// this is awaited when `erase_double_ack` is set, otherwise any stray bytes
+    /// are discarded so as not to desynchronise following commands.
+    ///
+    /// Returns the number of flash pages, or `None` if the geometry is unknown.
+    pub fn erase_all(&mut self) -> Result<Option<usize>, Error<E>> {
+        // Write command
+        self.write_cmd(Command::Erase)?;
+        self.await_ack(Command::Erase)?;
+
+        self.write_bytes(&[0xFF, 0x00])?;
+        self.await_cmd_ack_timeout(Command::Erase, self.options.erase_timeout_ms)?;
+
+        if self.options.erase_double_ack {
+            self.await_cmd_ack_timeout(Command::Erase, self.options.erase_timeout_ms)?;
+        } else {
+            self.drain_rx()?;
+        }
+
+        if self.options.verify_erase {
+            let count = self.geometry.ok_or(Error::UnknownGeometry)?.page_count();
+            self.verify_erased(0..count)?;
+        }
+
+        Ok(self.geometry.map(|g| g.page_count() as usize))
+    }
+
+    /// Check the first word of each provided page reads as erased (0xFF),
+    /// detecting erases ACKed by the bootloader but not performed (ie. for protected pages)
+    ///
+    /// This requires the device flash geometry
+    fn verify_erased<I: Iterator<Item = u16>>(&mut self, pages: I) -> Result<(), Error<E>> {
+        let geometry = match self.geometry {
+            Some(g) => g,
+            None => {
+                error!("Flash geometry required for erase verification");
+                return Err(Error::UnknownGeometry);
+            }
+        };
+
+        for index in pages {
+            let page = geometry.page(index).ok_or(Error::EraseFailed{ page: index })?;
+
+            let mut word = [0u8; 4];
+            self.read_mem_block(page.addr, &mut word)?;
+
+            if word != [0xFF; 4] {
+                error!("Page {} at 0x{:08x} not erased (read {:02x?})", index, page.addr, word);
+                return Err(Error::EraseFailed{ page: index });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read memory from the device
+    pub fn read(&mut self, addr: u32, data: &mut [u8]) -> Result<(), Error<E>> {
+        self.read_with_progress(addr, data, ())
+    }
+
+    /// Read memory from the device into a newly allocated buffer
+    #[cfg(feature = "std")]
+    pub fn read_to_vec(&mut self, addr: u32, len: usize) -> Result<Vec<u8>, Error<E>> {
+        let mut data = vec![0u8; len];
+
+        self.read(addr, &mut data)?;
+
+        Ok(data)
+    }
+
+    /// Read memory from the device, reporting progress following each block
+    pub fn read_with_progress<R: Progress>(&mut self, addr: u32, data: &mut [u8], mut progress: R) -> Result<(), Error<E>> {
+        self.check_range(addr, data.len())?;
+
+        let mut index = 0;
+        let total = data.len();
+
+        while index < total {
+            let a = addr + index as u32;
+
+            let n = self.options.read_block_len(a, total - index);
+
+            debug!("Read chunk at 0x{:08x}, length: {}", a, n);
+
+            self.read_mem_block(a, &mut data[index..index + n])?;
+
+            index += n;
+
+            progress.on_chunk(index, total);
+        }
+
+        Ok(())
+    }
+
+    fn read_mem_block(&mut self, addr: u32, data: &mut [u8]) -> Result<(), Error<E>> {
+        // Check readout protection up front (once per connection), so a protected device
+        // fails immediately rather than after exhausting retries
+        if self.read_protected()? {
+            error!("Read rejected, readout protection is active");
+            return Err(Error::ReadProtected);
+        }
+
+        self.with_retries(|s| {
+            s.try_read_mem_block(addr, data)?;
+
+            // ReadMemory responses have no checksum, so compare a second read where enabled
+            if s.options.verify_reads {
+                let mut buff = [0u8; MAX_CHUNK];
+                let b = &mut buff[..data.len()];
+
+                s.try_read_mem_block(addr, b)?;
+
+                if b != data {
+                    warn!("Read mismatch at 0x{:08x} (length {})", addr, data.len());
+                    return Err(Error::InvalidResponse);
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Check whether readout protection is active, caching the result until the next connection
+    ///
+    /// The read protection status bytes returned by [`Programmer::version_read_status`] are fixed
+    /// at 0x00 by the USART bootloader (see AN3155), however the ReadMemory command is NACKed while
+    /// readout protection is active. This issues the command and checks the response, where this is
+    /// ACKed the read is aborted with an invalid address checksum, returning the bootloader to
+    /// awaiting commands.
+    pub fn read_protected(&mut self) -> Result<bool, Error<E>> {
+        if let Some(p) = self.read_protection {
+            return Ok(p);
+        }
+
+        let p = self.probe_read_protection()?;
+        self.read_protection = Some(p);
+
+        Ok(p)
+    }
+
+    /// Issue a ReadMemory command to check for readout protection
+    fn probe_read_protection(&mut self) -> Result<bool, Error<E>> {
+        self.drain_rx()?;
+
+        self.write_cmd(Command::ReadMemory)?;
+
+        match self.await_ack(Command::ReadMemory) {
+            Ok(()) => (),
+            Err(Error::Nack{ .. }) => return Ok(true),
+            Err(e) => return Err(e),
+        }
+
+        let mut frame = encode_address(0);
+        frame[4] = !frame[4];
+
+        self.write_bytes(&frame)?;
+
+        match self.await_ack(Command::ReadMemory) {
+            Err(Error::Nack{ .. }) => Ok(false),
+            Ok(()) => {
+                warn!("Aborted read unexpectedly ACKed");
+                Err(Error::InvalidResponse)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    fn try_read_mem_block(&mut self, addr: u32, data: &mut [u8]) -> Result<(), Error<E>> {
+        assert!(data.len() <= 256, "block size must be less than 256 bytes");
+
+        // Write read command and await ack, the bootloader NACKs this when
+        // readout protection is active (see `read_mem_block`)
+        self.write_cmd(Command::ReadMemory)?;
+        self.await_ack(Command::ReadMemory)?;
+
+        // Write start address + xor checksum and await ack, failing immediately
+        // on NACK (ie. for an invalid address) rather than awaiting data
+        self.write_bytes(&encode_address(addr))?;
+
+        if let Err(e) = self.await_ack(Command::ReadMemory) {
+            error!("Read address 0x{:08x} rejected: {}", addr, e);
+            return Err(e);
+        }
+
+        // Write read length and checksum and await ack
+        self.write_bytes(&encode_read_length(data.len()))?;
+
+        if let Err(e) = self.await_ack(Command::ReadMemory) {
+            error!("Read length {} at 0x{:08x} rejected: {}", data.len(), addr, e);
+            return Err(e);
+        }
+
+        // Read response data, with the response timeout applying between bytes
+        // so a short response fails after a single timeout period
+        self.read_exact(data)?;
+
+        Ok(())
+    }
+
+    /// Write memory to the device
+    pub fn write(&mut self, addr: u32, data: &[u8]) -> Result<(), Error<E>> {
+        self.write_with_progress(addr, data, ())
+    }
+
+    /// Write memory to the device, reporting progress following each block
+    pub fn write_with_progress<R: Progress>(&mut self, addr: u32, data: &[u8], progress: R) -> Result<(), Error<E>> {
+        self.write_resumable(addr, data, progress)
+            .map(|_| ())
+            .map_err(|(_, e)| e)
+    }
+
+    /// Write memory to the device, reporting progress following each block and returning
+    /// the number of bytes written, or on failure the number of bytes successfully written
+    /// alongside the error
+    ///
+    /// An interrupted write can be resumed by writing `data[written..]` at `addr + written`.
+    pub fn write_resumable<R: Progress>(&mut self, addr: u32, data: &[u8], mut progress: R) -> Result<usize, (usize, Error<E>)> {
+        self.check_range(addr, data.len()).map_err(|e| (0, e))?;
+        self.check_write_alignment(addr).map_err(|e| (0, e))?;
+        self.check_vectors(addr, data, data.len());
+
+        let mut index = 0;
+        let total = data.len();
+
+        let mut skipped = 0;
+
+        while index < total {
+            let a = addr + index as u32;
+            let n = self.options.write_block_len(a, total - index);
+
+            match self.write_block_aligned(a, &data[index..index + n], index + n == total) {
+                Ok(true) => (),
+                Ok(false) => skipped += 1,
+                Err(e) => {
+                    error!("Write failed after {} of {} bytes", index, total);
+                    return Err((index, e));
+                },
+            }
+
+            index += n;
+
+            progress.on_chunk(index, total);
+        }
+
+        if skipped > 0 {
+            debug!("Skipped {} blank blocks", skipped);
+        }
+
+        Ok(total)
+    }
+
+    /// Write memory to the device, reading up to `total` bytes a block at a time
+    /// from the provided source
+    #[cfg(feature = "std")]
+    pub fn write_stream<S: std::io::Read>(&mut self, addr: u32, src: S, total: usize) -> Result<(), Error<E>> {
+        self.write_stream_with_progress(addr, src, total, ())
+    }
+
+    /// Write memory to the device from the provided source, reporting progress following each block
+    #[cfg(feature = "std")]
+    pub fn write_stream_with_progress<S: std::io::Read, R: Progress>(&mut self, addr: u32, src: S, total: usize, progress: R) -> Result<(), Error<E>> {
+        self.write_stream_resumable(addr, src, total, progress)
+            .map(|_| ())
+            .map_err(|(_, e)| e)
+    }
+
+    /// Write memory to the device from the provided source, returning the number of bytes
+    /// written, or on failure the number of bytes successfully written alongside the error
+    ///
+    /// See [`Programmer::write_resumable`] for resuming an interrupted write.
+    #[cfg(feature = "std")]
+    pub fn write_stream_resumable<S: std::io::Read, R: Progress>(&mut self, addr: u32, mut src: S, total: usize, mut progress: R) -> Result<usize, (usize, Error<E>)> {
+        self.check_range(addr, total).map_err(|e| (0, e))?;
+        self.check_write_alignment(addr).map_err(|e| (0, e))?;
+
+        let mut index = 0;
+        let mut skipped = 0;
+        let mut buff = [0u8; MAX_CHUNK];
+
+        while index < total {
+            let b = &mut buff[..self.options.write_block_len(addr + index as u32, total - index)];
+
+            // Fill the block, allowing for short reads
+            let mut n = 0;
+            while n < b.len() {
+                match src.read(&mut b[n..]) {
+                    Ok(0) => break,
+                    Ok(v) => n += v,
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+                    Err(e) => return Err((index, Error::Io(e.kind()))),
+                }
+            }
+
+            if n == 0 {
+                warn!("Source ended after {} of {} bytes", index, total);
+                break;
+            }
+
+            if index == 0 {
+                self.check_vectors(addr, &b[..n], total);
+            }
+
+            // A short read means the source has ended, so this is also the final block
+            let last = n < b.len() || index + n == total;
+
+            match self.write_block_aligned(addr + index as u32, &b[..n], last) {
+                Ok(true) => (),
+                Ok(false) => skipped += 1,
+                Err(e) => {
+                    error!("Write failed after {} of {} bytes", index, total);
+                    return Err((index, e));
+                },
+            }
+
+            index += n;
+
+            progress.on_chunk(index, total);
+        }
+
+        if skipped > 0 {
+            debug!("Skipped {} blank blocks", skipped);
+        }
+
+        Ok(index)
+    }
+
+    /// Check the region `[addr, addr+len)` is within device flash or SRAM
+    /// (where the flash geometry is known)
+    fn check_range(&self, addr: u32, len: usize) -> Result<(), Error<E>> {
+        check_range(self.geometry.as_ref(), addr, len)
+    }
+
+    /// Check a write start address meets the write alignment, unless `align_writes` is set
+    /// or the address is known to be in SRAM
+    fn check_write_alignment(&self, addr: u32) -> Result<(), Error<E>> {
+        check_write_alignment(&self.options, self.geometry.as_ref(), addr)
+    }
+
+    /// Check the initial SP and reset vector at the start of a write to the flash base
+    /// look plausible (where `sanity_check_vectors` is set), warning if not
+    fn check_vectors(&self, addr: u32, head: &[u8], len: usize) {
+        let base = self.geometry.map(|g| g.base).unwrap_or(geometry::FLASH_BASE);
+        if !self.options.sanity_check_vectors || addr != base || head.len() < 8 {
+            return;
+        }
+
+        let sp = u32::from_le_bytes([head[0], head[1], head[2], head[3]]);
+        let reset = u32::from_le_bytes([head[4], head[5], head[6], head[7]]);
+
+        // SP is pre-decremented so may sit at the end of SRAM
+        let sp_ok = match self.geometry {
+            Some(g) => sp > g.ram_base && sp <= g.ram_base + g.ram_size,
+            None => sp > geometry::RAM_BASE && sp <= 0x4000_0000,
+        };
+        if !sp_ok {
+            warn!("Initial SP 0x{:08x} is not in SRAM, image may not be bootable", sp);
+        }
+
+        // Reset handler must be a thumb address within the written region
+        let end = addr as u64 + len as u64;
+        if reset & 1 == 0 || (reset & !1) < addr || (reset & !1) as u64 >= end {
+            warn!("Reset vector 0x{:08x} is not within the written region (0x{:08x} - 0x{:08x}), image may not be bootable", reset, addr, end);
+        }
+    }
+
+    /// Write a single block, rounding an unaligned start address down to the write alignment
+    /// and re-writing the leading bytes read back from the device (where `align_writes` is set)
+    fn write_block_aligned(&mut self, addr: u32, chunk: &[u8], last: bool) -> Result<bool, Error<E>> {
+        let lead = self.options.write_lead(addr);
+        if lead == 0 {
+            return self.write_block(addr, chunk, last);
+        }
+
+        let start = addr - lead as u32;
+
+        debug!("Aligning write at 0x{:08x} to 0x{:08x}", addr, start);
+
+        let mut buff = [0u8; MAX_CHUNK];
+        self.read_mem_block(start, &mut buff[..lead])?;
+        buff[lead..lead + chunk.len()].copy_from_slice(chunk);
+
+        self.write_block(start, &buff[..lead + chunk.len()], last)
+    }
+
+    /// Write a single block, padding the final block of a write to the write alignment
+    /// and skipping blank blocks where enabled, returning false if the block was skipped
+    ///
+    /// Only the final block is padded, as [`Options::validate`] ensures the block size
+    /// is a multiple of the write alignment
+    fn write_block(&mut self, addr: u32, chunk: &[u8], last: bool) -> Result<bool, Error<E>> {
+        if self.options.skip_blank && chunk.iter().all(|b| *b == 0xFF) {
+            trace!("Skipping blank chunk at 0x{:08x}", addr);
+            return Ok(false);
+        }
+
+        debug!("Write chunk at 0x{:08x}, length: {}", addr, chunk.len());
+
+        let padded = self.options.padded_len(chunk.len(), last);
+
+        if padded == chunk.len() {
+            self.write_mem_block(addr, chunk)?;
+        } else {
+            // Pad partial words with 0xFF to match erased flash
+            info!("Padding final block from {} to {} bytes", chunk.len(), padded);
+
+            let mut buff = [0xFFu8; MAX_CHUNK];
+            buff[..chunk.len()].copy_from_slice(chunk);
+
+            self.write_mem_block(addr, &buff[..padded])?;
+        }
+
+        Ok(true)
+    }
+
+    /// Write memory to the device, reading back and comparing the written data
+    pub fn write_verified(&mut self, addr: u32, data: &[u8]) -> Result<(), Error<E>> {
+        self.write(addr, data)?;
+
+        self.verify(addr, data)
+    }
+
+    /// Read back memory from the device and compare against the provided data
+    pub fn verify(&mut self, addr: u32, data: &[u8]) -> Result<(), Error<E>> {
+        debug!("Verifying {} bytes at 0x{:08x}", data.len(), addr);
+
+        let mut index = 0;
+        let mut buff = [0u8; VERIFY_CHUNK];
+
+        for chunk in data.chunks(VERIFY_CHUNK) {
+            let a = addr + index as u32;
+            let b = &mut buff[..chunk.len()];
+
+            self.read_mem_block(a, b)?;
+
+            // Report the absolute address of the first mismatch
+            if let Some(i) = chunk.iter().zip(b.iter()).position(|(x, y)| x != y) {
+                error!("Verify mismatch at 0x{:08x}", a + i as u32);
+                return Err(Error::VerifyMismatch{ addr: a + i as u32 });
+            }
+
+            index += chunk.len();
+        }
+
+        Ok(())
+    }
+
+    /// Read back memory from the device and compare against the provided data,
+    /// returning the offsets of all differing bytes
+    ///
+    /// Unlike [`Programmer::verify`] this continues past the first mismatch,
+    /// with the offsets reported capped at [`MAX_COMPARE_MISMATCHES`].
+    #[cfg(feature = "std")]
+    pub fn compare(&mut self, addr: u32, expected: &[u8]) -> Result<Vec<usize>, Error<E>> {
+        debug!("Comparing {} bytes at 0x{:08x}", expected.len(), addr);
+
+        let mut index = 0;
+        let mut buff = [0u8; VERIFY_CHUNK];
+        let mut mismatches = vec![];
+
+        for chunk in expected.chunks(VERIFY_CHUNK) {
+            let b = &mut buff[..chunk.len()];
+
+            self.read_mem_block(addr + index as u32, b)?;
+
+            let diff = chunk.iter().zip(b.iter()).enumerate()
+                .filter(|(_, (x, y))| x != y)
+                .map(|(i, _)| index + i);
+            mismatches.extend(diff.take(MAX_COMPARE_MISMATCHES - mismatches.len()));
+
+            if mismatches.len() >= MAX_COMPARE_MISMATCHES {
+                warn!("Compare stopped after {} mismatches", mismatches.len());
+                break;
+            }
+
+            index += chunk.len();
+        }
+
+        Ok(mismatches)
+    }
+
+    /// Read back a region of memory and compare its CRC32 against the expected value
+    ///
+    /// This uses the STM32 hardware CRC parameters (see [`crc32`]), allowing verification
+    /// against a checksum produced at build time.
+    pub fn verify_crc(&mut self, addr: u32, expected_crc: u32, len: usize) -> Result<(), Error<E>> {
+        debug!("Verifying CRC over {} bytes at 0x{:08x}", len, addr);
+
+        let mut index = 0;
+        let mut crc = CRC32_INIT;
+        let mut buff = [0u8; MAX_CHUNK];
+
+        while index < len {
+            let b = &mut buff[..usize::min(len - index, MAX_CHUNK)];
+
+            self.read(addr + index as u32, b)?;
+
+            crc = crc32_update(crc, b);
+            index += b.len();
+        }
+
+        if crc != expected_crc {
+            error!("CRC mismatch (expected 0x{:08x}, actual 0x{:08x})", expected_crc, crc);
+            return Err(Error::CrcMismatch{ expected: expected_crc, actual: crc });
+        }
+
+        Ok(())
+    }
+
+    /// Read the 96-bit unique device ID for the provided chip ID
+    pub fn unique_id(&mut self, chip_id: u16) -> Result<[u8; 12], Error<E>> {
+        let geometry = geometry::geometry_for(chip_id).ok_or(Error::UnknownGeometry)?;
+
+        debug!("Reading unique ID at 0x{:08x}", geometry.uid_base);
+
+        let mut uid = [0u8; 12];
+        self.read_mem_block(geometry.uid_base, &mut uid)?;
+
+        Ok(uid)
+    }
+
+    /// Read the option bytes for the provided chip ID
+    #[cfg(feature = "std")]
+    pub fn read_option_bytes(&mut self, chip_id: u16) -> Result<Vec<u8>, Error<E>> {
+        let geometry = geometry::geometry_for(chip_id).ok_or(Error::UnknownGeometry)?;
+
+        debug!("Reading {} option bytes at 0x{:08x}", geometry.option_size, geometry.option_base);
+
+        let mut data = vec![0u8; geometry.option_size as usize];
+
+        self.read_mem_block(geometry.option_base, &mut data)?;
+
+        Ok(data)
+    }
+
+    /// Write the option bytes for the provided chip ID
+    ///
+    /// This must be the entire option byte region, the bootloader performs a system
+    /// reset following the write after which the connection is re-initialised.
+    pub fn write_option_bytes(&mut self, chip_id: u16, data: &[u8]) -> Result<(), Error<E>> {
+        let geometry = geometry::geometry_for(chip_id).ok_or(Error::UnknownGeometry)?;
+
+        if data.len() != geometry.option_size as usize {
+            error!("Option bytes must be {} bytes (got {})", geometry.option_size, data.len());
+            return Err(Error::OutOfRange{ addr: geometry.option_base, len: data.len() });
+        }
+
+        debug!("Writing {} option bytes at 0x{:08x}", data.len(), geometry.option_base);
+
+        self.write_mem_block(geometry.option_base, data)?;
+
+        debug!("Option bytes written, reconnecting to bootloader");
+
+        self.reconnect()
+    }
+
+    /// Check whether a region of memory is blank (all 0xFF)
+    pub fn is_blank(&mut self, addr: u32, len: usize) -> Result<bool, Error<E>> {
+        let a = self.find_non_blank(addr, len)?;
+
+        Ok(a.is_none())
+    }
+
+    /// Find the address of the first non-blank (not 0xFF) byte in a region of memory
+    pub fn find_non_blank(&mut self, addr: u32, len: usize) -> Result<Option<u32>, Error<E>> {
+        let mut index = 0;
+        let mut buff = [0u8; MAX_CHUNK];
+
+        while index < len {
+            let a = addr + index as u32;
+            let b = &mut buff[..usize::min(self.options.block_size, len - index)];
+
+            self.read_mem_block(a, b)?;
+
+            if let Some(i) = b.iter().position(|v| *v != 0xFF) {
+                debug!("Non-blank byte 0x{:02x} at 0x{:08x}", b[i], a + i as u32);
+                return Ok(Some(a + i as u32));
+            }
+
+            index += b.len();
+        }
+
+        Ok(None)
+    }
+
+    /// Write data to device SRAM (for example, to stage a loader to execute with [`Programmer::go`])
+    ///
+    /// This requires the device geometry to validate the region is within SRAM
+    pub fn write_ram(&mut self, addr: u32, data: &[u8]) -> Result<(), Error<E>> {
+        let geometry = self.geometry.ok_or(Error::UnknownGeometry)?;
+
+        if !geometry.contains_ram(addr, data.len()) {
+            error!("Region 0x{:08x} (length {}) is not within SRAM", addr, data.len());
+            return Err(Error::OutOfRange{ addr, len: data.len() });
+        }
+
+        self.write(addr, data)
+    }
+
+    /// Flash data to the device, erasing the pages spanned by `[addr, addr+len)`
+    /// then writing and optionally verifying the data.
+    ///
+    /// This requires the device flash geometry, and will refuse to erase pages
+    /// not entirely covered by the region unless `erase_unaligned` is set.
+    #[cfg(feature = "std")]
+    pub fn flash(&mut self, addr: u32, data: &[u8], verify: bool) -> Result<(), Error<E>> {
+        let geometry = match self.geometry {
+            Some(g) => g,
+            None => {
+                error!("Flash geometry required for flash operation");
+                return Err(Error::UnknownGeometry);
+            }
+        };
+
+        if !geometry.is_aligned(addr, data.len()) && !self.options.erase_unaligned {
+            error!("Region 0x{:08x} (length {}) is not page aligned", addr, data.len());
+            return Err(Error::Unaligned{ addr, len: data.len() });
+        }
+
+        // Erase spanned pages
+        self.erase_region(addr, data.len())?;
+
+        // Write (and verify) data
+        match verify {
+            true => self.write_verified(addr, data),
+            false => self.write(addr, data),
+        }
+    }
+
+    /// Flash a region of memory (see [`Programmer::flash`]), retrying the entire operation
+    /// up to `attempts` times with a full reset and reconnect between attempts
+    #[cfg(feature = "std")]
+    pub fn flash_with_retries(&mut self, addr: u32, data: &[u8], verify: bool, attempts: u8) -> Result<(), Error<E>> {
+        let attempts = attempts.max(1);
+        let mut i = 1;
+
+        loop {
+            info!("Flash attempt {}/{}", i, attempts);
+
+            // Geometry and alignment errors will not resolve on retry
+            let retry = |e: &Error<E>| !matches!(e, Error::UnknownGeometry | Error::Unaligned{ .. } | Error::OutOfRange{ .. });
+
+            match self.flash(addr, data, verify) {
+                Ok(()) => return Ok(()),
+                Err(e) if i < attempts && retry(&e) => {
+                    warn!("Flash attempt {}/{} failed: {}", i, attempts, e);
+
+                    self.delay.delay_ms(self.options.retry_delay_ms);
+
+                    if let Err(e) = self.reconnect() {
+                        warn!("Reconnect failed: {}", e);
+                    }
+
+                    i += 1;
+                },
+                Err(e) => {
+                    error!("Flash failed after {} attempt(s): {}", i, e);
+                    return Err(e);
+                },
+            }
+        }
+    }
+
+    /// Flash a (potentially multi-segment) firmware image
+    #[cfg(feature = "std")]
+    pub fn flash_image(&mut self, image: &FirmwareImage, verify: bool) -> Result<(), Error<E>> {
+        self.flash_image_with_progress(image, verify, ())
+    }
+
+    /// Flash a (potentially multi-segment) firmware image, erasing only the pages
+    /// spanned by each flash segment then writing and optionally verifying each segment.
+    ///
+    /// Progress is reported cumulatively over all segments, and memory between
+    /// segments is left untouched. As with [`Programmer::flash`] segments that only
+    /// partially cover a page are refused unless `erase_unaligned` is set.
+    #[cfg(feature = "std")]
+    pub fn flash_image_with_progress<R: Progress>(&mut self, image: &FirmwareImage, verify: bool, mut progress: R) -> Result<(), Error<E>> {
+        let geometry = match self.geometry {
+            Some(g) => g,
+            None => {
+                error!("Flash geometry required for flash operation");
+                return Err(Error::UnknownGeometry);
+            }
+        };
+
+        // Collect pages spanned by flash segments, erasing each page only once
+        // so segments sharing a page are not erased after writing
+        let mut pages = std::collections::BTreeSet::new();
+
+        for (addr, data) in image.segments.iter().filter(|(a, d)| geometry.contains_flash(*a, d.len())) {
+            if !geometry.is_aligned(*addr, data.len()) && !self.options.erase_unaligned {
+                error!("Region 0x{:08x} (length {}) is not page aligned", addr, data.len());
+                return Err(Error::Unaligned{ addr: *addr, len: data.len() });
+            }
+
+            pages.extend(geometry.pages_spanned(*addr, data.len()).map(|p| p.index));
+        }
+
+        if !pages.is_empty() {
+            let pages: Vec<u16> = pages.into_iter().collect();
+
+            debug!("Erasing pages: {:?}", pages);
+
+            self.erase_page_list(&pages)?;
+        }
+
+        // Write (and verify) each segment
+        let total = image.total_bytes();
+        let mut offset = 0;
+
+        for (addr, data) in &image.segments {
+            debug!("Writing segment of {} bytes at 0x{:08x}", data.len(), addr);
+
+            self.write_with_progress(*addr, data, |done, _| progress.on_chunk(offset + done, total))?;
+
+            if verify {
+                self.verify(*addr, data)?;
+            }
+
+            offset += data.len();
+        }
+
+        Ok(())
+    }
+
+    fn write_mem_block(&mut self, addr: u32, data: &[u8]) -> Result<(), Error<E>> {
+        self.with_retries(|s| s.try_write_mem_block(addr, data))
+    }
+
+    fn try_write_mem_block(&mut self, addr: u32, data: &[u8]) -> Result<(), Error<E>> {
+        assert!(data.len() <= 256, "block size must be less than 256 bytes");
+
+        // Write read command and await ack
+        self.write_cmd(Command::WriteMemory)?;
+        self.await_ack(Command::WriteMemory)?;
+        
+
+        // Write start address + xor checksum and await ack
+        self.write_bytes(&encode_address(addr))?;
+
+        self.await_ack(Command::WriteMemory)?;
+
+
+        // Set write length, data, and checksum and await ack
+        for d in encode_write_data(data) {
+            self.write_byte(d)?;
+        }
+        self.flush()?;
+
+        self.await_ack(Command::WriteMemory)?;
+
+
+        Ok(())
+    }
+
+    /// Jump to the application at the provided address
+    ///
+    /// Some bootloaders transfer control before sending the final ACK,
+    /// so a timeout awaiting this is not treated as an error.
+    pub fn go(&mut self, addr: u32) -> Result<(), Error<E>> {
+        self.go_with_ack(addr, false)
+    }
+
+    /// Jump to the application at the provided address, optionally requiring the final ACK
+    pub fn go_with_ack(&mut self, addr: u32, expect_ack: bool) -> Result<(), Error<E>> {
+        // Write go command and await ack
+        self.write_cmd(Command::Go)?;
+        self.await_ack(Command::Go)?;
+
+        // Write start address + xor checksum
+        self.write_bytes(&encode_address(addr))?;
+
+        // Await final ack (if sent)
+        match self.await_ack(Command::Go) {
+            Ok(()) => Ok(()),
+            Err(Error::Timeout) if !expect_ack => {
+                debug!("No ACK following go, assuming jump succeeded");
+                Ok(())
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Enable write protection for the provided flash sectors
+    ///
+    /// The bootloader performs a system reset following this command,
+    /// so no further communication is attempted after the final ACK.
+    pub fn write_protect(&mut self, sectors: &[u8]) -> Result<(), Error<E>> {
+        // Sector count is encoded as N-1, so 1-256 sectors may be provided
+        if sectors.is_empty() || sectors.len() > 256 {
+            error!("Invalid write protect sector count {} (1-256 sectors)", sectors.len());
+            return Err(Error::BufferLength);
+        }
+
+        // Write command
+        self.write_cmd(Command::WriteProtect)?;
+        self.await_ack(Command::WriteProtect)?;
+
+        // Write number of sectors and sector list, checksum covers both
+        let len = (sectors.len() - 1) as u8;
+        let csum = len ^ xor_checksum(sectors);
+
+        self.write_byte(len)?;
+        for s in sectors {
+            self.write_byte(*s)?;
+        }
+        self.write_byte(csum)?;
+        self.flush()?;
+
+        self.await_ack(Command::WriteProtect)?;
+
+        debug!("Write protection enabled, device will now reset");
+
+        Ok(())
+    }
+
+    /// Disable write protection for all flash sectors
+    ///
+    /// The bootloader performs a system reset following this command,
+    /// [`Programmer::reconnect`] must be called before issuing further commands.
+    pub fn write_unprotect(&mut self) -> Result<(), Error<E>> {
+        // Write command
+        self.write_cmd(Command::WriteUnprotect)?;
+        self.await_ack(Command::WriteUnprotect)?;
+
+        // Await second ack once protection is disabled
+        self.await_cmd_ack_timeout(Command::WriteUnprotect, self.options.erase_timeout_ms)?;
+
+        debug!("Write protection disabled, device will now reset");
+
+        Ok(())
+    }
+
+    /// Enable readout protection (RDP level 1)
+    ///
+    /// The bootloader performs a system reset following this command,
+    /// [`Programmer::reconnect`] must be called before issuing further commands.
+    pub fn readout_protect(&mut self) -> Result<(), Error<E>> {
+        // Write command
+        self.write_cmd(Command::ReadoutProtect)?;
+        self.await_ack(Command::ReadoutProtect)?;
+
+        // Await second ack once protection is enabled
+        self.await_cmd_ack_timeout(Command::ReadoutProtect, self.options.erase_timeout_ms)?;
+
+        debug!("Readout protection enabled, device will now reset");
+
+        self.read_protection = None;
 
         Ok(())
     }
 
+    /// Disable readout protection, erasing the entire flash
+    ///
+    /// The bootloader performs a system reset following this command,
+    /// after which the connection is re-initialised.
+    pub fn readout_unprotect(&mut self) -> Result<(), Error<E>> {
+        // Write command
+        self.write_cmd(Command::ReadoutUnprotect)?;
+        self.await_ack(Command::ReadoutUnprotect)?;
+
+        // Await second ack once mass erase is complete
+        self.await_cmd_ack_timeout(Command::ReadoutUnprotect, self.options.erase_timeout_ms)?;
+
+        debug!("Readout protection disabled, reconnecting to bootloader");
+
+        self.reconnect()
+    }
+
+    /// Recover a readout protected device, disabling readout protection (mass erasing
+    /// the flash) and reconnecting following the resulting system reset
+    ///
+    /// Reconnection is attempted at least [`UNBRICK_ATTEMPTS`] times (or `init_attempts`
+    /// if greater) as the device may be slow to restart following the option byte reload.
+    /// The first word of each flash page is then checked blank, returning `false` where
+    /// this was not possible due to an unknown flash geometry.
+    pub fn unbrick(&mut self) -> Result<bool, Error<E>> {
+        let attempts = self.options.init_attempts;
+        self.options.init_attempts = attempts.max(UNBRICK_ATTEMPTS);
+
+        let r = self.readout_unprotect();
+
+        self.options.init_attempts = attempts;
+        r?;
+
+        let geometry = match self.geometry {
+            Some(g) => g,
+            None => match self.detect_geometry()? {
+                Some(g) => g,
+                None => {
+                    warn!("Unknown flash geometry, skipping blank check");
+                    return Ok(false);
+                },
+            },
+        };
+
+        debug!("Checking {} flash pages are blank", geometry.page_count());
+
+        self.verify_erased(0..geometry.page_count())?;
+
+        Ok(true)
+    }
+
+    /// Execute an operation, retrying on NACK (or invalid response) up to `max_retries` times,
+    /// and on timeout where `resync_on_timeout` is set
+    fn with_retries<T, F>(&mut self, mut f: F) -> Result<T, Error<E>>
+    where
+        F: FnMut(&mut Self) -> Result<T, Error<E>>,
+    {
+        let mut attempts = 0;
+
+        loop {
+            let e = match f(self) {
+                Err(e) => e,
+                r => return r,
+            };
+
+            match self.options.retry_action(&e, attempts) {
+                Some(Retry::Delay) => {
+                    attempts += 1;
+
+                    warn!("{}, retrying ({}/{})", e, attempts, self.options.max_retries);
+
+                    self.delay.delay_ms(self.options.retry_delay_ms);
+                },
+                Some(Retry::Resync) => {
+                    attempts += 1;
+
+                    warn!("Timeout, re-synchronising and retrying ({}/{})", attempts, self.options.max_retries);
+
+                    self.resync()?;
+                },
+                None => return Err(e),
+            }
+        }
+    }
+
+    /// Set a custom reset handler, used in place of RTS / DTR for subsequent resets
+    #[cfg(feature = "std")]
+    pub fn set_reset_handler(&mut self, reset_handler: ResetHandler<E>) {
+        self.reset_handler = Some(reset_handler);
+    }
+
     /// Reset the device using RTS while asserting DTR entering the bootloading or application
+    ///
+    /// If a custom reset handler is set this is called instead,
+    /// and if `no_reset` is set this does nothing.
     pub fn reset(&mut self, bootloader: bool) -> Result<(), Error<E>> {
+        if self.options.no_reset {
+            debug!("Skipping reset (no_reset set)");
+            return Ok(());
+        }
+
+        #[cfg(feature = "std")]
+        if let Some(h) = self.reset_handler.as_mut() {
+            let r = match bootloader {
+                true => ResetRequest::Bootloader,
+                false => ResetRequest::Application,
+            };
+
+            debug!("Calling reset handler ({:?})", r);
+
+            return h(r).map_err(Error::Serial);
+        }
+
+        let (rts, dtr) = (!self.options.rts_invert, !self.options.dtr_invert);
+
         // Assert RTS to reset the device
-        self.port.set_rts(true)?;
+        self.port.set_rts(rts)?;
 
         // Wait a moment for the device to turn off
-        self.delay.delay_ms(10u32);
+        self.delay.delay_ms(self.options.reset_assert_ms);
 
         if bootloader {
             // DTR signals to use bootloader
-            self.port.set_dtr(true)?;
+            self.port.set_dtr(dtr)?;
         }
 
         // RTS re-enables device
-        self.port.set_rts(false)?;
+        self.port.set_rts(!rts)?;
 
         // Wait for bootloader or app to start
         self.delay.delay_ms(self.options.init_delay_ms);
 
         if bootloader {
             // De-assert DTR
-            self.port.set_dtr(false)?;
+            self.port.set_dtr(!dtr)?;
         }
 
         Ok(())
     }
 
-    /// Fetch device chip ID (not-working)
+    /// Reset the device into the bootloader, equivalent to `reset(true)`
+    pub fn reset_into_bootloader(&mut self) -> Result<(), Error<E>> {
+        self.reset(true)
+    }
+
+    /// Reset the device into the application, equivalent to `reset(false)`
+    pub fn reset_into_app(&mut self) -> Result<(), Error<E>> {
+        self.reset(false)
+    }
+
+    /// Fetch device chip ID
     pub fn chip_id(&mut self) -> Result<u16, Error<E>> {
         // Write GetID command
         self.write_cmd(Command::GetId)?;
         
         // Await ACK
-        self.await_ack()?;
+        self.await_ack(Command::GetId)?;
 
         // Read N (static sized)
         let n = self.read_char()? as usize + 1;
 
         debug!("Reading {} byte chip ID", n);
 
-        // Read chip ID
+        // Read chip ID (MSB first)
         let mut v: u16 = 0;
-        for i in 0..n {
+        for _ in 0..n {
             let c = self.read_char()?;
-            v |= (c as u16) << (i * 8);
+            v = (v << 8) | c as u16;
         }
 
         // Await ACK
-        self.await_ack()?;
+        self.await_ack(Command::GetId)?;
 
         Ok(v)
     }
 
     /// Write a bootloader command to the device
     pub fn write_cmd(&mut self, command: Command) -> Result<(), Error<E>> {
-        // Write command
-        let c1 = command.clone() as u8;
-        let c2 = !c1;
+        // Refuse commands the bootloader does not advertise
+        if let (Some(false), Some(version)) = (self.supports(command), self.version) {
+            error!("{} command not supported by bootloader (v{}.{})", command, version >> 4, version & 0x0F);
+            return Err(Error::Unsupported{ command, version });
+        }
+
+        debug!("Writing command {}", command);
+
+        self.write_raw_cmd(command as u8)
+    }
+
+    /// Write a raw command byte (framed with its complement) to the device
+    ///
+    /// This allows vendor specific or otherwise unmodelled commands to be issued,
+    /// see [`Programmer::await_ack_timeout`] for awaiting the response.
+    pub fn write_raw_cmd(&mut self, cmd: u8) -> Result<(), Error<E>> {
+        let c = encode_raw_command(cmd);
 
-        debug!("Writing command {:?} [0x{:02x}, 0x{:02x}]", command, c1, c2);
+        debug!("Writing command bytes [0x{:02x}, 0x{:02x}]", c[0], c[1]);
 
-        block!(self.port.write(c1))?;
-        block!(self.port.write(c2))?;
-        block!(self.port.flush())?;
+        for d in &c {
+            self.write_byte(*d)?;
+        }
+        self.flush()?;
 
         Ok(())
     }
@@ -435,72 +2284,636 @@ where
         debug!("Writing bytes: 0x{:02x?}", data);
 
         for d in data {
-            block!(self.port.write(*d))?;
+            self.write_byte(*d)?;
         }
 
-        block!(self.port.flush())?;
+        self.flush()?;
 
         Ok(())
     }
 
     /// Write data with xor checksum
     pub fn write_bytes_csum(&mut self, data: &[u8]) -> Result<(), Error<E>> {
-        let mut csum = 0x00;
-        for d in data {
-            csum ^= *d;
-        }
+        let csum = xor_checksum(data);
 
         info!("Writing data with checksum: {:02x?} ({:02x})", data, csum);
 
         for d in data {
-            block!(self.port.write(*d))?;
+            self.write_byte(*d)?;
         }
 
-        block!(self.port.write(csum))?;
-        block!(self.port.flush())?;
+        self.write_byte(csum)?;
+        self.flush()?;
+
+        Ok(())
+    }
+
+    /// Write a single byte to the device, failing if the port does not accept it within the response timeout
+    fn write_byte(&mut self, b: u8) -> Result<(), Error<E>> {
+        self.write_byte_timeout(b, self.options.response_timeout_ms)
+    }
+
+    /// Write a single byte to the device with the provided timeout
+    fn write_byte_timeout(&mut self, b: u8, timeout_ms: u32) -> Result<(), Error<E>> {
+        self.poll_timeout(|p| p.write(b), timeout_ms)?;
+
+        #[cfg(feature = "std")]
+        self.transcript.tx(b);
+
+        if self.options.inter_byte_delay_us > 0 {
+            self.delay.delay_us(self.options.inter_byte_delay_us);
+        }
 
         Ok(())
     }
 
+    /// Flush the serial port, failing if this does not complete within the response timeout
+    fn flush(&mut self) -> Result<(), Error<E>> {
+        self.poll_timeout(|p| p.flush(), self.options.response_timeout_ms)
+    }
+
+    /// Poll a non-blocking serial operation until it completes or the timeout elapses
+    fn poll_timeout<F>(&mut self, mut f: F, timeout_ms: u32) -> Result<(), Error<E>>
+    where
+        F: FnMut(&mut P) -> nb::Result<(), E>,
+    {
+        let mut t = PollTimer::new();
+
+        loop {
+            match f(&mut self.port) {
+                Err(nb::Error::WouldBlock) => (),
+                Err(nb::Error::Other(e)) => return Err(e.into()),
+                Ok(()) => return Ok(()),
+            };
+
+            self.delay.delay_ms(self.options.poll_delay_ms);
+
+            if t.expired(self.options.poll_delay_ms, timeout_ms) {
+                error!("Transmit timeout");
+                return Err(Error::Timeout);
+            }
+        }
+    }
+
     /// Read a single character from the device
     pub fn read_char(&mut self) -> Result<u8, Error<E>> {
-        let mut t = 0;
+        self.read_char_timeout(self.options.response_timeout_ms)
+    }
+
+    /// Discard any pending received bytes, returning the number discarded
+    pub fn drain_rx(&mut self) -> Result<usize, Error<E>> {
+        let mut n = 0;
+
+        loop {
+            match self.port.read() {
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(e)) => return Err(e.into()),
+                Ok(v) => {
+                    trace!("Discarding received byte: 0x{:02x}", v);
+                    n += 1;
+                },
+            }
+        }
+
+        if n > 0 {
+            debug!("Discarded {} pending bytes", n);
+        }
+
+        Ok(n)
+    }
+
+    /// Read a single character from the device with the provided timeout
+    fn read_char_timeout(&mut self, timeout_ms: u32) -> Result<u8, Error<E>> {
+        let mut t = PollTimer::new();
+        let mut spins = 0;
 
         loop {
             // Attempt to read from serial port
             match self.port.read() {
                 Err(nb::Error::WouldBlock) => (),
                 Err(nb::Error::Other(e)) => return Err(e.into()),
-                Ok(v) => return Ok(v)
+                Ok(v) => {
+                    #[cfg(feature = "std")]
+                    self.transcript.rx(v);
+
+                    return Ok(v)
+                }
             };
 
-            // Wait for delay period
+            // Retry immediately for the first few attempts
+            if spins < POLL_SPIN_ATTEMPTS {
+                spins += 1;
+                continue;
+            }
+
+            // Then wait for delay period
             self.delay.delay_ms(self.options.poll_delay_ms);
-            t += self.options.poll_delay_ms;
 
-            if t > self.options.response_timeout_ms {
+            if t.expired(self.options.poll_delay_ms, timeout_ms) {
                 error!("Receive timeout");
                 return Err(Error::Timeout);
             }
         }
     }
 
+    /// Read enough characters from the device to fill the provided buffer
+    ///
+    /// This only waits while no data is available, with the response timeout
+    /// applying to the period since the last received character.
+    fn read_exact(&mut self, buff: &mut [u8]) -> Result<(), Error<E>> {
+        let mut index = 0;
+        let mut t = PollTimer::new();
+        let mut spins = 0;
+
+        while index < buff.len() {
+            // Read all available characters
+            match self.port.read() {
+                Err(nb::Error::WouldBlock) => (),
+                Err(nb::Error::Other(e)) => return Err(e.into()),
+                Ok(v) => {
+                    #[cfg(feature = "std")]
+                    self.transcript.rx(v);
+
+                    buff[index] = v;
+                    index += 1;
+                    t.reset();
+                    spins = 0;
+                    continue;
+                }
+            };
+
+            // Retry immediately for the first few attempts
+            if spins < POLL_SPIN_ATTEMPTS {
+                spins += 1;
+                continue;
+            }
+
+            // Then wait for delay period
+            self.delay.delay_ms(self.options.poll_delay_ms);
+
+            if t.expired(self.options.poll_delay_ms, self.options.response_timeout_ms) {
+                error!("Receive timeout ({} of {} bytes)", index, buff.len());
+                return Err(Error::Timeout);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Await an ack from the bootloader
-    fn await_ack(&mut self) -> Result<(), Error<E>> {
-        let v = self.read_char()?;
-        match v {
-            UART_ACK => {
+    fn await_ack(&mut self, command: Command) -> Result<(), Error<E>> {
+        self.await_cmd_ack_timeout(command, self.options.response_timeout_ms)
+    }
+
+    /// Await an ack from the bootloader with the provided timeout
+    ///
+    /// This allows custom sequences to be built using [`Programmer::write_raw_cmd`] and
+    /// [`Programmer::write_bytes`], returning [`Error::RawNack`] in the event of a NACK.
+    pub fn await_ack_timeout(&mut self, timeout_ms: u32) -> Result<(), Error<E>> {
+        self.read_ack(None, timeout_ms)
+    }
+
+    /// Await an ack to the provided command with the provided timeout
+    fn await_cmd_ack_timeout(&mut self, command: Command, timeout_ms: u32) -> Result<(), Error<E>> {
+        self.read_ack(Some(command), timeout_ms)
+    }
+
+    /// Read and match an ack, reporting a NACK against `command` (or as [`Error::RawNack`] if `None`)
+    fn read_ack(&mut self, command: Option<Command>, timeout_ms: u32) -> Result<(), Error<E>> {
+        let v = self.read_char_timeout(timeout_ms)?;
+
+        #[cfg(feature = "std")]
+        match command {
+            Some(c) => self.transcript.complete(c),
+            None => self.transcript.complete("Raw"),
+        }
+
+        match (ack_from_byte(v), command) {
+            (Ok(()), _) => {
                 trace!("Received ACK!");
                 Ok(())
             },
-            UART_NACK => {
-                trace!("Received NACK?!");
-                Err(Error::Nack)
+            (Err(AckError::Nack), Some(command)) => {
+                trace!("Received NACK to {}?!", command);
+                Err(Error::Nack{ command })
             },
-            _ => {
+            (Err(AckError::Nack), None) => {
+                trace!("Received NACK to raw command?!");
+                Err(Error::RawNack)
+            },
+            (Err(AckError::Invalid(v)), _) => {
                 error!("Unexpected response: 0x{:02x}", v);
                 Err(Error::InvalidResponse)
             }
         }
     }
 }
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::mock::*;
+
+    /// Create a programmer over the provided mock port without connecting
+    fn programmer(port: MockSerial) -> Programmer<MockSerial, MockDelay, MockError> {
+        let options = Options{ no_connect: true, ..Default::default() };
+
+        Programmer::new(port, MockDelay::default(), options).unwrap()
+    }
+
+    #[test]
+    fn new_fails_without_ack() {
+        let port = MockSerial::new();
+
+        let r = Programmer::new(port, MockDelay::default(), Options::default());
+
+        assert!(matches!(r, Err(Error::NoAck)));
+    }
+
+    #[test]
+    fn new_propagates_write_errors() {
+        let mut port = MockSerial::new();
+        port.fail_writes = true;
+
+        let r = Programmer::new(port, MockDelay::default(), Options::default());
+
+        assert!(matches!(r, Err(Error::Serial(MockError::Injected))));
+    }
+
+    #[test]
+    fn write_propagates_write_errors() {
+        let mut port = MockSerial::new();
+        port.fail_writes = true;
+
+        let mut p = programmer(port);
+
+        assert_eq!(p.write(0x0800_0000, &[0x01, 0x02, 0x03, 0x04]), Err(Error::Serial(MockError::Injected)));
+    }
+
+    #[test]
+    fn new_accepts_nack_to_discovery() {
+        let mut port = MockSerial::new();
+        port.expect(&[UART_DISC, 0x00, 0xFF])
+            .queue_after(1, &[UART_NACK])
+            .queue_after(3, &[UART_ACK, 0x01, 0x31, 0x00, UART_ACK]);
+
+        let p = Programmer::new(port, MockDelay::default(), Options::default()).unwrap();
+
+        assert_eq!(p.bootloader_version(), Some(0x31));
+        assert!(p.port.done());
+    }
+
+    #[test]
+    fn handshake_without_reset() {
+        let mut port = MockSerial::new();
+        port.expect(&[UART_DISC])
+            .queue_after(1, &[UART_NACK]);
+
+        let mut p = programmer(port);
+
+        assert_eq!(p.handshake(), Ok(true));
+        assert!(p.port.done());
+        assert!(p.port.rts.is_empty());
+        assert!(p.port.dtr.is_empty());
+    }
+
+    #[test]
+    fn handshake_rejects_garbage() {
+        let mut port = MockSerial::new();
+        port.expect(&[UART_DISC])
+            .queue_after(1, &[0x55]);
+
+        let mut p = programmer(port);
+
+        assert_eq!(p.handshake(), Ok(false));
+
+        let mut port = MockSerial::new();
+        port.expect(&[UART_DISC]);
+
+        let mut p = programmer(port);
+
+        assert_eq!(p.handshake(), Ok(false));
+    }
+
+    #[test]
+    fn chip_id_msb_first() {
+        let mut port = MockSerial::new();
+        port.expect(&[0x02, 0xFD])
+            .queue_after(2, &[UART_ACK, 0x01, 0x04, 0x13, UART_ACK]);
+
+        let mut p = programmer(port);
+
+        assert_eq!(p.chip_id(), Ok(0x0413));
+        assert!(p.port.done());
+    }
+
+    #[test]
+    fn no_reset_skips_modem_lines() {
+        let mut port = MockSerial::new();
+        port.expect(&[UART_DISC, 0x00, 0xFF])
+            .queue_after(1, &[UART_ACK])
+            .queue_after(3, &[UART_ACK, 0x01, 0x31, 0x00, UART_ACK]);
+
+        let options = Options{ no_reset: true, ..Default::default() };
+        let mut p = Programmer::new(port, MockDelay::default(), options).unwrap();
+
+        p.reset_into_bootloader().unwrap();
+        p.reset_into_app().unwrap();
+
+        assert!(p.port.done());
+        assert!(p.port.rts.is_empty());
+        assert!(p.port.dtr.is_empty());
+    }
+
+    #[test]
+    fn erase_pages_framing() {
+        let mut port = MockSerial::new();
+        port.expect(&[0x43, 0xBC, 0x02, 0x01, 0x02, 0x03, 0x02])
+            .queue_after(2, &[UART_ACK])
+            .queue_after(7, &[UART_ACK]);
+
+        let mut p = programmer(port);
+
+        assert_eq!(p.erase_pages(&[1, 2, 3]), Ok(3));
+        assert!(p.port.done());
+    }
+
+    #[test]
+    fn erase_pages_rejects_invalid_lengths() {
+        let mut p = programmer(MockSerial::new());
+
+        assert_eq!(p.erase_pages(&[]), Err(Error::BufferLength));
+
+        let pages: Vec<u8> = (0..=255).collect();
+        assert_eq!(p.erase_pages(&pages), Err(Error::BufferLength));
+
+        // Nothing should be sent for an invalid page list
+        assert!(p.port.written.is_empty());
+    }
+
+    #[test]
+    fn erase_page_ranges() {
+        for (offset, count, frame) in [
+            (2, 4, &[0x03, 0x02, 0x03, 0x04, 0x05, 0x03][..]),
+            (0, 1, &[0x00, 0x00, 0x00][..]),
+            (250, 6, &[0x05, 0xFA, 0xFB, 0xFC, 0xFD, 0xFE, 0xFF, 0x04][..]),
+        ] {
+            let mut port = MockSerial::new();
+            port.expect(&[0x43, 0xBC])
+                .expect(frame)
+                .queue_after(2, &[UART_ACK])
+                .queue_after(2 + frame.len(), &[UART_ACK]);
+
+            let mut p = programmer(port);
+
+            assert_eq!(p.erase(offset, count), Ok(count as usize));
+            assert!(p.port.done(), "erase({}, {})", offset, count);
+        }
+    }
+
+    #[test]
+    fn erase_rejects_page_overflow() {
+        let mut p = programmer(MockSerial::new());
+
+        assert_eq!(p.erase(200, 100), Err(Error::BufferLength));
+        assert!(p.port.written.is_empty());
+    }
+
+    #[test]
+    fn write_protect_rejects_invalid_lengths() {
+        let mut p = programmer(MockSerial::new());
+
+        assert_eq!(p.write_protect(&[]), Err(Error::BufferLength));
+        assert_eq!(p.write_protect(&[0u8; 257]), Err(Error::BufferLength));
+        assert!(p.port.written.is_empty());
+    }
+
+    #[test]
+    fn write_unprotect_awaits_both_acks() {
+        let mut port = MockSerial::new();
+        port.expect(&[0x73, 0x8C])
+            .queue_after(2, &[UART_ACK, UART_ACK]);
+
+        let mut p = programmer(port);
+
+        assert_eq!(p.write_unprotect(), Ok(()));
+        assert!(p.port.done());
+    }
+
+    #[test]
+    fn write_unprotect_nack() {
+        for rx in [&[UART_NACK][..], &[UART_ACK, UART_NACK][..]] {
+            let mut port = MockSerial::new();
+            port.queue_after(2, rx);
+
+            let mut p = programmer(port);
+
+            assert_eq!(p.write_unprotect(), Err(Error::Nack{ command: Command::WriteUnprotect }));
+            assert!(p.port.done());
+        }
+    }
+
+    #[test]
+    fn write_unprotect_stray_byte() {
+        let mut port = MockSerial::new();
+        port.queue_after(2, &[UART_ACK, 0x55]);
+
+        let mut p = programmer(port);
+
+        assert_eq!(p.write_unprotect(), Err(Error::InvalidResponse));
+    }
+
+    #[test]
+    fn erase_all_consumes_double_ack() {
+        for erase_double_ack in [false, true] {
+            let mut port = MockSerial::new();
+            port.expect(&[0x43, 0xBC, 0xFF, 0x00])
+                .queue_after(2, &[UART_ACK])
+                .queue_after(4, &[UART_ACK, UART_ACK]);
+
+            let options = Options{ no_connect: true, erase_double_ack, ..Default::default() };
+            let mut p = Programmer::new(port, MockDelay::default(), options).unwrap();
+
+            assert_eq!(p.erase_all(), Ok(None));
+            assert!(p.port.done(), "erase_double_ack: {}", erase_double_ack);
+        }
+    }
+
+    #[test]
+    fn read_protection_checked_up_front() {
+        let mut port = MockSerial::new();
+        port.expect(&[0x11, 0xEE])
+            .queue_after(2, &[UART_NACK]);
+
+        // Retries are not attempted for a protected device
+        let options = Options{ no_connect: true, max_retries: 3, resync_on_timeout: true, ..Default::default() };
+        let mut p = Programmer::new(port, MockDelay::default(), options).unwrap();
+        let mut buff = [0u8; 4];
+
+        assert_eq!(p.read(0x0800_0000, &mut buff), Err(Error::ReadProtected));
+        // The cached result is used for following reads
+        assert_eq!(p.read(0x0800_0000, &mut buff), Err(Error::ReadProtected));
+        assert!(p.port.done());
+    }
+
+    #[test]
+    fn read_nack_not_protected() {
+        let mut port = MockSerial::new();
+        port.expect(&[0x11, 0xEE, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x11, 0xEE])
+            .queue_after(2, &[UART_ACK])
+            .queue_after(7, &[UART_NACK])
+            .queue_after(9, &[UART_NACK]);
+
+        let mut p = programmer(port);
+        let mut buff = [0u8; 4];
+
+        assert_eq!(p.read(0x0800_0000, &mut buff), Err(Error::Nack{ command: Command::ReadMemory }));
+        assert_eq!(p.read_protected(), Ok(false));
+        assert!(p.port.done());
+    }
+
+    #[test]
+    fn read_nack_retried() {
+        let mut port = MockSerial::new();
+        port.queue_after(2, &[UART_ACK])
+            .queue_after(7, &[UART_NACK])
+            .queue_after(9, &[UART_NACK])
+            .queue_after(11, &[UART_ACK])
+            .queue_after(16, &[UART_ACK])
+            .queue_after(18, &[UART_ACK, 0x01, 0x02, 0x03, 0x04]);
+
+        let options = Options{ no_connect: true, max_retries: 1, ..Default::default() };
+        let mut p = Programmer::new(port, MockDelay::default(), options).unwrap();
+        let mut buff = [0u8; 4];
+
+        assert_eq!(p.read(0x0800_0000, &mut buff), Ok(()));
+        assert_eq!(buff, [0x01, 0x02, 0x03, 0x04]);
+        assert!(p.port.done());
+    }
+
+    #[test]
+    fn new_rejects_invalid_block_size() {
+        for block_size in [0, MAX_CHUNK + 1] {
+            let options = Options{ no_connect: true, block_size, ..Default::default() };
+            let r = Programmer::new(MockSerial::new(), MockDelay::default(), options);
+            assert_eq!(r.err(), Some(Error::BufferLength));
+        }
+
+        let options = Options{ no_connect: true, block_size: 2, align_writes: true, write_alignment: 4, ..Default::default() };
+        let r = Programmer::new(MockSerial::new(), MockDelay::default(), options);
+        assert_eq!(r.err(), Some(Error::BufferLength));
+
+        let options = Options{ no_connect: true, block_size: 100, write_alignment: 8, ..Default::default() };
+        let r = Programmer::new(MockSerial::new(), MockDelay::default(), options);
+        assert_eq!(r.err(), Some(Error::BufferLength));
+
+        let options = Options{ no_connect: true, block_size: 1, write_alignment: 1, ..Default::default() };
+        assert!(Programmer::new(MockSerial::new(), MockDelay::default(), options).is_ok());
+    }
+
+    #[test]
+    fn write_pads_final_block_only() {
+        let data: Vec<u8> = (0..10).collect();
+
+        let mut expected = vec![0x31, 0xCE];
+        expected.extend(encode_address(0x0800_0000));
+        expected.extend(encode_write_data(&data[..8]));
+        expected.extend([0x31, 0xCE]);
+        expected.extend(encode_address(0x0800_0008));
+        expected.extend(encode_write_data(&[0x08, 0x09, 0xFF, 0xFF]));
+
+        let mut port = MockSerial::new();
+        port.expect(&expected)
+            .queue_after(2, &[UART_ACK])
+            .queue_after(7, &[UART_ACK])
+            .queue_after(17, &[UART_ACK])
+            .queue_after(19, &[UART_ACK])
+            .queue_after(24, &[UART_ACK])
+            .queue_after(30, &[UART_ACK]);
+
+        let options = Options{ no_connect: true, block_size: 8, write_alignment: 4, ..Default::default() };
+        let mut p = Programmer::new(port, MockDelay::default(), options).unwrap();
+
+        assert_eq!(p.write(0x0800_0000, &data), Ok(()));
+        assert!(p.port.done());
+    }
+
+    #[test]
+    fn builder_checks_block_size() {
+        let r: Result<_, Error<MockError>> = Options::builder().block_size(0).build();
+        assert_eq!(r, Err(Error::BufferLength));
+
+        let r: Result<_, Error<MockError>> = Options::builder().block_size(MAX_CHUNK + 1).build();
+        assert_eq!(r, Err(Error::BufferLength));
+
+        let r: Result<_, Error<MockError>> = Options::builder().block_size(64).build();
+        assert_eq!(r.map(|o| o.block_size), Ok(64));
+    }
+
+    #[test]
+    fn dry_run_reads_back_writes() {
+        use std::io::ErrorKind as IoErrorKind;
+
+        let mut p: Programmer<_, _, IoErrorKind> = Programmer::new(dry_run::DryRun::new(), MockDelay::default(), Options::default()).unwrap();
+
+        let data = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        p.write(0x0800_0000, &data).unwrap();
+
+        let mut buff = [0u8; 12];
+        p.read(0x0800_0000, &mut buff).unwrap();
+        assert_eq!(&buff[..8], &data);
+        assert_eq!(&buff[8..], &[0xFF; 4]);
+
+        p.extended_erase_pages(&[0]).unwrap();
+        p.read(0x0800_0000, &mut buff).unwrap();
+        assert_eq!(buff, [0xFF; 12]);
+    }
+
+    #[test]
+    fn raw_command_ack() {
+        let mut port = MockSerial::new();
+        port.queue_after(2, &[UART_ACK])
+            .queue_after(4, &[UART_NACK]);
+
+        let mut p = programmer(port);
+
+        p.write_raw_cmd(0x31).unwrap();
+        assert_eq!(p.await_ack_timeout(100), Ok(()));
+
+        p.write_raw_cmd(0xA5).unwrap();
+        assert_eq!(p.await_ack_timeout(100), Err(Error::RawNack));
+
+        assert_eq!(p.port.written, vec![0x31, 0xCE, 0xA5, 0x5A]);
+        assert!(p.port.done());
+    }
+
+    #[cfg(feature = "linux")]
+    #[test]
+    fn serial_config_mapping() {
+        let options = Options{ data_bits: 7, parity: Parity::Even, stop_bits: 2, ..Default::default() };
+        let c = options.serial_config([5, 6, 7, 8], ['n', 'o', 'e'], [1, 2]).unwrap();
+        assert_eq!(c, (7, 'e', 2));
+
+        let options = Options{ data_bits: 9, ..Default::default() };
+        assert!(options.serial_config([5, 6, 7, 8], ['n', 'o', 'e'], [1, 2]).is_err());
+
+        let options = Options{ stop_bits: 0, ..Default::default() };
+        assert!(options.serial_config([5, 6, 7, 8], ['n', 'o', 'e'], [1, 2]).is_err());
+    }
+
+    #[test]
+    fn erase_page_list_rejects_mass_erase_length() {
+        let mut port = MockSerial::new();
+
+        // Get response advertising standard erase only
+        port.queue_after(2, &[UART_ACK, 0x02, 0x31, 0x00, 0x43, UART_ACK]);
+
+        let mut p = programmer(port);
+
+        let pages: Vec<u16> = (0..256).collect();
+        assert_eq!(p.erase_page_list(&pages), Err(Error::BufferLength));
+
+        // Only the Get command should have been sent
+        assert_eq!(p.port.written, &[0x00, 0xFF]);
+    }
+}
@@ -6,43 +6,120 @@ use linux_embedded_hal::serial_core::{
     SerialPortSettings as _, StopBits,
 };
 use linux_embedded_hal::{Delay, Serial};
+use log::{debug, info};
 
-use crate::{Options, Programmer, SerialPort};
+use crate::{Error, Options, Programmer, SerialPort};
 
 impl SerialPort<IoErrorKind> for Serial {
     fn set_rts(&mut self, level: bool) -> Result<(), IoErrorKind> {
-        self.0.set_rts(level).unwrap();
-        Ok(())
+        self.0.set_rts(level).map_err(|e| std::io::Error::from(e).kind())
     }
     fn set_dtr(&mut self, level: bool) -> Result<(), IoErrorKind> {
-        self.0.set_dtr(level).unwrap();
-        Ok(())
+        self.0.set_dtr(level).map_err(|e| std::io::Error::from(e).kind())
     }
 }
 
+/// Baud rates attempted (in order) when auto-detecting
+pub const AUTO_BAUD_RATES: &[usize] = &[115200, 57600, 38400, 9600];
+
 impl Programmer<Serial, Delay, IoErrorKind> {
-    /// Create a new linux serial port programmer instance
+    /// Create a new linux serial port programmer instance,
+    /// attempting each of [`AUTO_BAUD_RATES`] if no baud rate is provided
     pub fn linux<P: AsRef<Path>>(
+        port: P,
+        baud: Option<usize>,
+        options: Options,
+    ) -> Result<Self, anyhow::Error> {
+        if let Some(baud) = baud {
+            return Self::linux_baud(port.as_ref(), baud, options);
+        }
+
+        for baud in AUTO_BAUD_RATES {
+            debug!("Attempting connection at {} baud", baud);
+
+            match Self::linux_baud(port.as_ref(), *baud, options.clone()) {
+                Ok(p) => {
+                    info!("Connected at {} baud", baud);
+                    return Ok(p);
+                },
+                Err(e) => debug!("Connection at {} baud failed: {:?}", baud, e),
+            }
+        }
+
+        Err(anyhow::anyhow!("Unable to detect bootloader baud rate"))
+    }
+
+    /// Create a new programmer instance using a pre-opened and configured serial port,
+    /// connecting to the attached bootloader (unless `no_connect` is set)
+    ///
+    /// Port settings are not modified, so line options (`data_bits`, `parity`, `stop_bits`)
+    /// must be applied by the caller.
+    pub fn from_serial(port: Serial, delay: Delay, options: Options) -> Result<Self, Error<IoErrorKind>> {
+        Self::new(port, delay, options)
+    }
+
+    /// Probe for a bootloader on the provided port, returning whether one responds
+    ///
+    /// This only performs the discovery handshake, the device is not reset and no
+    /// further commands are issued. No response, or an unexpected response, within
+    /// the response timeout is reported as `false`.
+    pub fn probe<P: AsRef<Path>>(
         port: P,
         baud: usize,
         options: Options,
+    ) -> Result<bool, anyhow::Error> {
+        let options = Options{ no_connect: true, ..options };
+
+        let mut p = Self::linux_baud(port.as_ref(), baud, options)?;
+
+        Ok(p.handshake()?)
+    }
+
+    /// Reconfigure the serial port baud rate, re-running the connection handshake
+    /// (including device reset) at the new rate
+    ///
+    /// The bootloader only detects the baud rate on connection, so this is required
+    /// to switch to a faster rate following an initial connection.
+    pub fn set_baud(&mut self, baud: usize) -> Result<(), Error<IoErrorKind>> {
+        debug!("Switching to {} baud", baud);
+
+        let kind = |e| std::io::Error::from(e).kind();
+
+        let mut settings = self.port.0.read_settings().map_err(kind)?;
+        settings.set_baud_rate(BaudRate::from_speed(baud)).map_err(kind)?;
+        self.port.0.write_settings(&settings).map_err(kind)?;
+
+        self.reconnect()
+    }
+
+    /// Create a new linux serial port programmer instance with the provided baud rate
+    fn linux_baud(
+        port: &Path,
+        baud: usize,
+        options: Options,
     ) -> Result<Self, anyhow::Error> {
         // Open port
-        let mut port = Serial::open(port.as_ref())?;
+        let mut port = Serial::open(port)?;
 
         // Apply settings
         let mut settings = port.0.read_settings()?;
 
-        settings.set_char_size(CharSize::Bits8);
-        settings.set_stop_bits(StopBits::Stop1);
+        let (char_size, parity, stop_bits) = options.serial_config(
+            [CharSize::Bits5, CharSize::Bits6, CharSize::Bits7, CharSize::Bits8],
+            [Parity::ParityNone, Parity::ParityOdd, Parity::ParityEven],
+            [StopBits::Stop1, StopBits::Stop2],
+        )?;
+
+        settings.set_char_size(char_size);
+        settings.set_stop_bits(stop_bits);
         settings.set_baud_rate(BaudRate::from_speed(baud))?;
         settings.set_flow_control(FlowControl::FlowNone);
-        settings.set_parity(Parity::ParityEven);
+        settings.set_parity(parity);
 
         port.0.write_settings(&settings)?;
 
         // Return instance
-        let s = Self::new(port, Delay {}, options)?;
+        let s = Self::from_serial(port, Delay {}, options)?;
 
         Ok(s)
     }
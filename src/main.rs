@@ -1,13 +1,25 @@
-use std::{num::ParseIntError};
+use std::{io::{ErrorKind as IoErrorKind, Seek, SeekFrom}, num::ParseIntError, path::Path, time::Instant};
 
 #[macro_use]
 extern crate log;
 
 use anyhow::Context;
+use indicatif::{ProgressBar, ProgressStyle};
 use structopt::StructOpt;
-use simplelog::{Config, LevelFilter, SimpleLogger};
+use serde_json::{json, Map, Value};
+use simplelog::{Config, LevelFilter, SimpleLogger, WriteLogger};
 
-use stm32_uart_loader::{Options, Programmer};
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use linux_embedded_hal::Delay;
+
+use stm32_uart_loader::{Error, Options, Programmer, SerialPort};
+use stm32_uart_loader::geometry::Bank;
+use stm32_uart_loader::protocol::{Command, ExtendedEraseKind, MAX_ERASE_PAGES};
+use stm32_uart_loader::dry_run::DryRun;
+use stm32_uart_loader::image::FirmwareImage;
+use stm32_uart_loader::device::product_name;
+use stm32_uart_loader::geometry::FLASH_BASE;
+use stm32_uart_loader::linux::AUTO_BAUD_RATES;
 
 #[derive(Clone, Debug, StructOpt)]
 pub struct Args {
@@ -21,13 +33,40 @@ pub struct Args {
     #[structopt(long, default_value = "/dev/ttyUSB0")]
     port: String,
 
-    /// Serial port baud rate
+    /// Serial port baud rate, or 'auto' to detect
     #[structopt(long, default_value = "57600")]
-    baud: usize,
+    baud: String,
+
+    /// Log operations against an emulated bootloader without connecting to a device
+    #[structopt(long)]
+    dry_run: bool,
 
     /// Log level for console output
     #[structopt(long, default_value = "info")]
     log_level: LevelFilter,
+
+    /// Output format, 'text' for log output or 'json' for a machine-readable summary on stdout
+    #[structopt(long, default_value = "text")]
+    format: Format,
+}
+
+/// CLI output format
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err("format must be one of 'text' or 'json'"),
+        }
+    }
 }
 
 
@@ -42,9 +81,13 @@ pub enum Commands {
         #[structopt(long, parse(try_from_str=bytefmt::parse))]
         length: u64,
 
-        /// File to read data into
+        /// File to read data into (`-` for stdout)
+        #[structopt(long, required_unless="hex")]
+        file: Option<String>,
+
+        /// Print a hex dump of the data (to stdout unless `--file` is provided)
         #[structopt(long)]
-        file: String,
+        hex: bool,
     },
     Write {
         /// Offset from which to start memory write
@@ -54,18 +97,158 @@ pub enum Commands {
         /// File to read data from
         #[structopt(long)]
         file: String,
+
+        /// Read back and verify written data
+        #[structopt(long)]
+        verify: bool,
+
+        /// Erase flash pages spanned by each segment prior to writing, leaving gaps between segments untouched
+        #[structopt(long, conflicts_with="resume")]
+        erase: bool,
+
+        /// Resume an interrupted write, skipping the provided number of already written bytes
+        #[structopt(long, default_value="0")]
+        resume: usize,
+    },
+    /// Read back memory and compare against a file
+    Verify {
+        /// Offset of raw binary files (ignored for images with embedded addresses)
+        #[structopt(long, parse(try_from_str=u32_from_hex), default_value="0x08000000")]
+        offset: u32,
+
+        /// File to compare against
+        #[structopt(long)]
+        file: String,
+    },
+    /// Read back memory and report all bytes differing from a file
+    Compare {
+        /// Offset of raw binary files (ignored for images with embedded addresses)
+        #[structopt(long, parse(try_from_str=u32_from_hex), default_value="0x08000000")]
+        offset: u32,
+
+        /// File to compare against
+        #[structopt(long)]
+        file: String,
     },
     Erase {
         /// Offset from which to start memory read
         #[structopt(long, default_value="0")]
-        page_offset: u8,
+        page_offset: u16,
 
         /// Length of memory to read
+        #[structopt(long, required_unless_one=&["sectors", "pages", "bank"])]
+        page_count: Option<u16>,
+
+        /// Comma separated sector numbers to erase (F2/F4/F7), as an alternative to page offset / count
+        #[structopt(long, use_delimiter=true, conflicts_with="page-count")]
+        sectors: Vec<u8>,
+
+        /// Comma separated page numbers to erase, as an alternative to page offset / count
+        #[structopt(long, use_delimiter=true, conflicts_with_all=&["page-count", "sectors"])]
+        pages: Vec<u16>,
+
+        /// Use extended erase (two byte page numbers, v3.0+ bootloaders)
         #[structopt(long)]
-        page_count: u8,
+        extended: bool,
+
+        /// Erase an entire flash bank (1 or 2) on dual bank devices
+        #[structopt(long, possible_values=&["1", "2"], conflicts_with_all=&["page-count", "sectors", "pages"])]
+        bank: Option<u8>,
     },
     EraseAll,
-    //ChipId,
+    /// Read the entire device flash to a file
+    Dump {
+        /// File to write flash contents to
+        #[structopt(long)]
+        file: String,
+
+        /// Length of flash to read (detected from the chip ID if not provided)
+        #[structopt(long, parse(try_from_str=bytefmt::parse))]
+        length: Option<u64>,
+    },
+    /// Check a region of memory is blank (erased)
+    BlankCheck {
+        /// Offset from which to start blank check
+        #[structopt(long, parse(try_from_str=u32_from_hex), default_value="0x08000000")]
+        offset: u32,
+
+        /// Length of memory to check
+        #[structopt(long, parse(try_from_str=bytefmt::parse))]
+        length: u64,
+    },
+    /// Enable readout protection (RDP level 1)
+    ReadoutProtect,
+    /// Disable readout protection, erasing the entire flash
+    ReadoutUnprotect,
+    /// Recover a readout protected device, disabling readout protection and confirming the flash is blank
+    Unbrick,
+    /// Jump to the application at the provided address
+    Go {
+        /// Address of the application to jump to
+        #[structopt(long, parse(try_from_str=u32_from_hex), default_value="0x08000000")]
+        offset: u32,
+
+        /// Require the bootloader to ACK the jump (by default a missing ACK is treated as success)
+        #[structopt(long, overrides_with="no-expect-ack")]
+        expect_ack: bool,
+
+        /// Treat a missing ACK following the jump as success (the default, overrides `--expect-ack`)
+        #[structopt(long, overrides_with="expect-ack")]
+        no_expect_ack: bool,
+    },
+    /// Fetch bootloader version and supported commands
+    Info,
+    /// Fetch the device chip ID
+    ChipId {
+        /// Also read the 96-bit unique device ID
+        #[structopt(long)]
+        uid: bool,
+    },
+    /// Check whether a bootloader is present using only the discovery handshake (without reset),
+    /// exiting with an error if not
+    Probe,
+    /// Reset the device into the application (or bootloader) without connecting
+    Reset {
+        /// Reset into the bootloader rather than the application
+        #[structopt(long)]
+        bootloader: bool,
+    },
+}
+
+impl Commands {
+    /// Command name for output summaries
+    fn name(&self) -> &'static str {
+        match self {
+            Commands::Read{..} => "read",
+            Commands::Write{..} => "write",
+            Commands::Verify{..} => "verify",
+            Commands::Compare{..} => "compare",
+            Commands::Erase{..} => "erase",
+            Commands::EraseAll => "erase-all",
+            Commands::Dump{..} => "dump",
+            Commands::BlankCheck{..} => "blank-check",
+            Commands::ReadoutProtect => "readout-protect",
+            Commands::ReadoutUnprotect => "readout-unprotect",
+            Commands::Unbrick => "unbrick",
+            Commands::Go{..} => "go",
+            Commands::Info => "info",
+            Commands::ChipId{..} => "chip-id",
+            Commands::Probe => "probe",
+            Commands::Reset{..} => "reset",
+        }
+    }
+
+    /// Check whether the command writes data to stdout
+    fn writes_stdout(&self) -> bool {
+        match self {
+            Commands::Read{file, hex, ..} => match file.as_deref() {
+                Some("-") => true,
+                Some(_) => false,
+                None => *hex,
+            },
+            _ => false,
+        }
+    }
 }
 
 fn u32_from_hex(s: &str) -> Result<u32, ParseIntError> {
@@ -73,62 +256,647 @@ fn u32_from_hex(s: &str) -> Result<u32, ParseIntError> {
     u32::from_str_radix(s, 16)
 }
 
+/// Create a progress bar for a transfer, hidden if not enabled
+fn progress_bar(len: usize, enabled: bool) -> ProgressBar {
+    if !enabled {
+        return ProgressBar::hidden();
+    }
+
+    let pb = ProgressBar::new(len as u64);
+
+    pb.set_style(ProgressStyle::default_bar()
+        .template("{spinner:.green} [{elapsed_precise}] [{bar:60.cyan/blue}] {bytes}/{total_bytes} {bytes_per_sec} ({eta})")
+        .progress_chars("#>-"));
+
+    pb
+}
+
+/// Finish a progress bar, displaying the total bytes and elapsed time
+fn progress_finish(pb: &ProgressBar) {
+    pb.set_style(ProgressStyle::default_bar()
+        .template("Transferred {bytes} in {elapsed} ({bytes_per_sec})"));
+
+    pb.finish();
+}
+
+/// Write an `xxd` style hex dump of data read from the provided device address
+fn hex_dump(w: &mut impl std::io::Write, addr: u32, data: &[u8]) -> std::io::Result<()> {
+    for (i, line) in data.chunks(16).enumerate() {
+        write!(w, "{:08x}: ", addr as usize + i * 16)?;
+
+        for j in 0..16 {
+            match line.get(j) {
+                Some(b) => write!(w, "{:02x}", b)?,
+                None => write!(w, "  ")?,
+            }
+            if j % 2 == 1 {
+                write!(w, " ")?;
+            }
+        }
+
+        let ascii: String = line.iter()
+            .map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' })
+            .collect();
+        writeln!(w, " {}", ascii)?;
+    }
+
+    w.flush()
+}
+
+/// Fetch the lower case extension of a file
+fn extension(file: &str) -> Option<String> {
+    Path::new(file).extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+}
+
+/// Load a firmware image, parsing formats with embedded addresses
+/// or using raw data at the provided offset
+fn load_image(file: &str, offset: u32) -> Result<FirmwareImage, anyhow::Error> {
+    let data = std::fs::read(file)
+        .context("Failure reading from file")?;
+
+    let image = match extension(file).as_deref() {
+        Some("hex") | Some("ihex") => {
+            let text = String::from_utf8(data)
+                .context("Failure reading hex file")?;
+            FirmwareImage::from_ihex(&text)
+                .context("Failure parsing hex file")?
+        },
+        Some("srec") | Some("s19") | Some("s28") | Some("s37") | Some("mot") => {
+            let text = String::from_utf8(data)
+                .context("Failure reading srec file")?;
+            FirmwareImage::from_srec(&text)
+                .context("Failure parsing srec file")?
+        },
+        Some("elf") => {
+            FirmwareImage::from_elf(&data)
+                .context("Failure parsing elf file")?
+        },
+        _ => FirmwareImage::from_bin(&data, offset),
+    };
+
+    Ok(image)
+}
+
+/// Check whether a file is a firmware image format with embedded addresses
+fn is_image(file: &str) -> bool {
+    matches!(extension(file).as_deref(),
+        Some("hex") | Some("ihex") | Some("srec") | Some("s19") | Some("s28") | Some("s37") | Some("mot") | Some("elf"))
+}
+
+/// Build an error for an interrupted write, noting the offset from which to resume
+fn resume_error(written: usize, e: Error<IoErrorKind>) -> anyhow::Error {
+    anyhow::Error::new(e)
+        .context(format!("Error writing memory, {} bytes written (resume with `--resume {}`)", written, written))
+}
+
+fn baud_from_str(s: &str) -> Result<Option<usize>, ParseIntError> {
+    match s {
+        "auto" => Ok(None),
+        _ => s.parse().map(Some),
+    }
+}
+
 fn main() -> Result<(), anyhow::Error> {
     // Parse out arguments
     let o = Args::from_args();
 
-    // Configure logger
-    let _ = SimpleLogger::init(o.log_level, Config::default());
+    // Configure logger, using stderr when stdout is reserved for JSON output or read data
+    let _ = match o.format {
+        Format::Text if !o.command.writes_stdout() => SimpleLogger::init(o.log_level, Config::default()),
+        _ => WriteLogger::init(o.log_level, Config::default(), std::io::stderr()),
+    };
+
+    // Read data on stdout would be interleaved with the JSON summary
+    let r = if o.format == Format::Json && o.command.writes_stdout() {
+        Err(anyhow::anyhow!("JSON output requires read data to be written to a file (--file <path>)"))
+    } else {
+        run(&o)
+    };
+
+    if o.format == Format::Text {
+        return r.map(|_| ());
+    }
+
+    // Print JSON summary, exiting with an error code on failure
+    let mut summary = Map::new();
+    summary.insert("command".to_string(), json!(o.command.name()));
+
+    match r {
+        Ok(v) => {
+            summary.extend(v);
+            println!("{}", Value::Object(summary));
+        },
+        Err(e) => {
+            summary.insert("error".to_string(), json!(format!("{:#}", e)));
+            println!("{}", Value::Object(summary));
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
 
+/// Connect to the bootloader and execute the requested command
+fn run(o: &Args) -> Result<Map<String, Value>, anyhow::Error> {
     debug!("Connecting to bootloader");
 
-    let mut p = Programmer::linux(&o.port, o.baud, o.options.clone())
+    let baud = baud_from_str(&o.baud)
+        .context("Invalid baud rate")?;
+
+    // Skip the bootloader handshake where only resetting the device
+    let mut options = o.options.clone();
+    if let Commands::Reset{..} = o.command {
+        options.no_connect = true;
+    }
+
+    if o.dry_run {
+        info!("Dry run, no device will be connected");
+
+        let mut p = Programmer::new(DryRun::new(), Delay{}, options)
+            .context("Error connecting to dry run bootloader")?;
+
+        return execute(&mut p, o);
+    }
+
+    if let Commands::Probe = o.command {
+        return probe(o, baud);
+    }
+
+    let mut p = Programmer::linux(&o.port, baud, options)
         .context("Error connecting to bootloader")?;
 
+    execute(&mut p, o)
+}
+
+/// Probe for a bootloader without connecting, at the specified or each auto-detected baud rate
+fn probe(o: &Args, baud: Option<usize>) -> Result<Map<String, Value>, anyhow::Error> {
+    let rates = match baud {
+        Some(b) => vec![b],
+        None => AUTO_BAUD_RATES.to_vec(),
+    };
+
+    for baud in rates {
+        debug!("Probing {} at {} baud", o.port, baud);
+
+        // Errors at one rate do not prevent probing the remaining rates
+        match Programmer::probe(&o.port, baud, o.options.clone()) {
+            Ok(true) => {
+                info!("Bootloader present at {} baud", baud);
+
+                let mut summary = Map::new();
+                summary.insert("baud".to_string(), json!(baud));
+
+                return Ok(summary);
+            },
+            Ok(false) => debug!("No bootloader response at {} baud", baud),
+            Err(e) => warn!("Error probing at {} baud: {:?}", baud, e),
+        }
+    }
+
+    Err(anyhow::anyhow!("No bootloader detected on {}", o.port))
+}
+
+/// Execute the requested command
+///
+/// Returns a summary of the command results for JSON output
+fn execute<P, D>(p: &mut Programmer<P, D, IoErrorKind>, o: &Args) -> Result<Map<String, Value>, anyhow::Error>
+where
+    P: SerialPort<IoErrorKind>,
+    D: DelayMs<u32> + DelayUs<u32>,
+{
+    let start = Instant::now();
+    let mut bytes = 0;
+    let mut summary = Map::new();
+
     // Execute commands
     match &o.command {
-        Commands::Read{offset, length, file} => {
+        Commands::Read{offset, length, file, hex} => {
             info!("Reading {} bytes from memory at offset 0x{:08x}", length, offset);
 
             let mut data = vec![0u8; *length as usize];
-            p.read(*offset, &mut data).context("Error reading memory")?;
+
+            let pb = progress_bar(data.len(), !o.options.no_progress);
+            p.read_with_progress(*offset, &mut data, |done, _| pb.set_position(done as u64))
+                .context("Error reading memory")?;
+            progress_finish(&pb);
+
+            bytes += data.len();
+
+            let mut out = match file.as_deref() {
+                Some("-") | None => Box::new(std::io::stdout()) as Box<dyn std::io::Write>,
+                Some(f) => Box::new(std::fs::File::create(f).context("Failure creating file")?),
+            };
+
+            if *hex {
+                hex_dump(&mut out, *offset, &data)
+            } else {
+                out.write_all(&data)
+            }.context("Failure writing data")?;
+
+            info!("Read complete!");
+
+            if let Some(f) = file {
+                summary.insert("file".to_string(), json!(f));
+            }
+        },
+        Commands::Dump{file, length} => {
+            let length = match length {
+                Some(l) => *l as usize,
+                None => p.detect_geometry()
+                    .context("Error fetching chip ID")?
+                    .map(|g| g.size() as usize)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown flash size, specify --length"))?,
+            };
+
+            info!("Dumping {} bytes of flash to {}", length, file);
+
+            let mut data = vec![0u8; length];
+
+            let pb = progress_bar(data.len(), !o.options.no_progress);
+            p.read_with_progress(FLASH_BASE, &mut data, |done, _| pb.set_position(done as u64))
+                .context("Error reading memory")?;
+            progress_finish(&pb);
+
+            bytes += data.len();
 
             std::fs::write(file, data)
                 .context("Failure writing to file")?;
 
-            info!("Read complete!");
+            info!("Dump complete!");
+
+            summary.insert("file".to_string(), json!(file));
+        },
+
+        Commands::Write{offset, file, verify: false, erase: false, resume} if !is_image(file) => {
+            // Stream raw binaries directly from the file
+            let mut f = std::fs::File::open(file)
+                .context("Failure opening file")?;
+            let len = f.metadata()
+                .context("Failure reading file metadata")?.len() as usize;
+
+            if *resume > len {
+                return Err(anyhow::anyhow!("Resume offset {} exceeds file length {}", resume, len));
+            }
+
+            f.seek(SeekFrom::Start(*resume as u64))
+                .context("Failure seeking file")?;
+
+            info!("Writing {} bytes to memory at offset 0x{:08x}", len - resume, *offset + *resume as u32);
+
+            let pb = progress_bar(len, !o.options.no_progress);
+            pb.set_position(*resume as u64);
+
+            let r = p.write_stream_resumable(*offset + *resume as u32, std::io::BufReader::new(f), len - resume, |done, _| pb.set_position((resume + done) as u64));
+            progress_finish(&pb);
+
+            bytes += r.map_err(|e| resume_error(resume + e.0, e.1))?;
+
+            summary.insert("verified".to_string(), json!(false));
+
+            info!("Write complete!");
         },
+        Commands::Write{offset, file, verify, erase, resume} => {
+            let image = load_image(file, *offset)?;
+            let total = image.total_bytes();
+            let (start, end) = image.span();
+
+            info!("Writing {} bytes in {} segment(s) spanning 0x{:08x} - 0x{:08x}", total, image.segments.len(), start, end);
+            for (addr, data) in &image.segments {
+                debug!("Segment 0x{:08x} (length {})", addr, data.len());
+            }
+
+            if *resume > total {
+                return Err(anyhow::anyhow!("Resume offset {} exceeds image length {}", resume, total));
+            }
+
+            let pb = progress_bar(total, !o.options.no_progress);
+
+            if *erase {
+                if p.geometry().is_none() {
+                    p.detect_geometry()
+                        .context("Error fetching chip ID")?;
+                }
+
+                // Erase spanned pages then write and verify all segments
+                p.flash_image_with_progress(&image, *verify, |done, _| pb.set_position(done as u64))
+                    .context("Error flashing image")?;
+            } else {
+                let mut done = 0;
 
-        Commands::Write{offset, file} => {
-            let data = std::fs::read(file)
-                .context("Failure reading from file")?;
+                for (addr, data) in &image.segments {
+                    // Skip data already written prior to resuming
+                    let skip = usize::min(resume.saturating_sub(done), data.len());
+                    let (addr, data) = (*addr + skip as u32, &data[skip..]);
+                    done += skip;
 
-            info!("Writing {} bytes to memory at offset 0x{:08x}", data.len(), offset);
+                    if !data.is_empty() {
+                        p.write_resumable(addr, data, |n, _| pb.set_position((done + n) as u64))
+                            .map_err(|e| resume_error(done + e.0, e.1))?;
 
-            p.write(*offset, &data)
-                .context("Error writing memory")?;
+                        if *verify {
+                            p.verify(addr, data)
+                                .context("Error verifying memory")?;
+                        }
+                    }
+
+                    done += data.len();
+                }
+            }
+
+            progress_finish(&pb);
+
+            bytes += total - resume;
+
+            summary.insert("verified".to_string(), json!(verify));
 
             info!("Write complete!");
         },
-        Commands::Erase{page_offset, page_count} => {
-            info!("Erasing {} pages from index {}", page_count, page_offset);
+        Commands::Verify{offset, file} => {
+            let image = load_image(file, *offset)?;
+
+            for (addr, data) in &image.segments {
+                info!("Verifying {} bytes at offset 0x{:08x}", data.len(), addr);
+
+                match p.verify(*addr, data) {
+                    Ok(()) => (),
+                    Err(Error::VerifyMismatch{ addr }) => {
+                        return Err(anyhow::anyhow!("Mismatch at 0x{:08x}", addr));
+                    },
+                    Err(e) => return Err(e).context("Error verifying memory"),
+                }
+
+                bytes += data.len();
+            }
+
+            info!("Match");
+
+            summary.insert("verified".to_string(), json!(true));
+        },
+        Commands::Compare{offset, file} => {
+            let image = load_image(file, *offset)?;
+            let mut differ = 0;
+
+            for (addr, data) in &image.segments {
+                info!("Comparing {} bytes at offset 0x{:08x}", data.len(), addr);
+
+                let mismatches = p.compare(*addr, data)
+                    .context("Error comparing memory")?;
+
+                for m in &mismatches {
+                    debug!("Mismatch at 0x{:08x}", *addr as usize + m);
+                }
+
+                differ += mismatches.len();
+                bytes += data.len();
+            }
+
+            info!("{} of {} bytes differ", differ, bytes);
+
+            summary.insert("differ".to_string(), json!(differ));
+        },
+        Commands::Erase{bank: Some(b), ..} => {
+            let bank = match b {
+                1 => Bank::Bank1,
+                _ => Bank::Bank2,
+            };
+
+            info!("Erasing flash {:?}", bank);
+
+            // Geometry is used to check the bank exists
+            if p.geometry().is_none() {
+                p.detect_geometry()
+                    .context("Error fetching chip ID")?;
+            }
+
+            let n = p.erase_bank(bank)
+                .context("Error erasing bank")?;
+
+            if let Some(n) = n {
+                info!("Erased {} pages", n);
+                summary.insert("pages_erased".to_string(), json!(n));
+            }
+        },
+        Commands::Erase{sectors, ..} if !sectors.is_empty() => {
+            info!("Erasing sectors {:?}", sectors);
+
+            if p.geometry().is_none() {
+                p.detect_geometry()
+                    .context("Error fetching chip ID")?;
+            }
 
-            p.erase(*page_offset, *page_count)
-                .context("Error erasing pages")?;
+            let n = p.erase_sectors(sectors)
+                .context("Error erasing sectors")?;
+
+            info!("Erased {} sectors", n);
+            summary.insert("pages_erased".to_string(), json!(n));
+        },
+        Commands::Erase{page_offset, page_count, pages, extended, ..} => {
+            let pages: Vec<u16> = match page_count {
+                Some(n) => {
+                    let end = page_offset.checked_add(*n)
+                        .ok_or_else(|| anyhow::anyhow!("Page range exceeds page {}", u16::MAX))?;
+
+                    (*page_offset..end).collect()
+                },
+                None => {
+                    let mut sorted = pages.clone();
+                    sorted.sort_unstable();
+                    if let Some(w) = sorted.windows(2).find(|w| w[0] == w[1]) {
+                        return Err(anyhow::anyhow!("Duplicate page {} in erase list", w[0]));
+                    }
+
+                    if !extended && (pages.len() > 255 || pages.iter().any(|p| *p > 255)) {
+                        return Err(anyhow::anyhow!("Page list exceeds standard erase limits (255 pages numbered 0-255), use --extended"));
+                    }
+
+                    pages.clone()
+                },
+            };
+
+            if o.options.verify_erase && p.geometry().is_none() {
+                p.detect_geometry()
+                    .context("Error fetching chip ID")?;
+            }
+
+            if pages.is_empty() {
+                return Err(anyhow::anyhow!("No pages to erase"));
+            }
+
+            // Use extended erase where requested, pages exceed single byte addressing,
+            // or the bootloader only supports extended erase
+            let extended = *extended || pages.iter().any(|p| *p > 255) || p.supports(Command::Erase) == Some(false);
+
+            // 256 pages would be encoded as N-1 = 0xFF, requesting a mass erase
+            if !extended && pages.len() > MAX_ERASE_PAGES {
+                return Err(anyhow::anyhow!("Standard erase is limited to {} pages, use --extended", MAX_ERASE_PAGES));
+            }
+
+            info!("Erasing {} pages: {:?}", pages.len(), pages);
+
+            let n = if extended {
+                debug!("Using extended erase");
+
+                p.extended_erase_pages(&pages)
+                    .context("Error erasing pages")?
+            } else {
+                let pages: Vec<u8> = pages.iter().map(|p| *p as u8).collect();
+
+                p.erase_pages(&pages)
+                    .context("Error erasing pages")?
+            };
+
+            info!("Erased {} pages", n);
+            summary.insert("pages_erased".to_string(), json!(n));
         },
         Commands::EraseAll => {
             info!("Erasing entire device flash");
 
-            p.erase_all()
-                .context("Error erasing pages")?;
+            if o.options.verify_erase && p.geometry().is_none() {
+                p.detect_geometry()
+                    .context("Error fetching chip ID")?;
+            }
+
+            let n = if p.supports(Command::Erase) == Some(false) {
+                debug!("Using extended erase");
+
+                p.extended_erase(ExtendedEraseKind::Mass)
+                    .context("Error erasing pages")?;
+
+                p.geometry().map(|g| g.page_count() as usize)
+            } else {
+                p.erase_all()
+                    .context("Error erasing pages")?
+            };
+
+            if let Some(n) = n {
+                info!("Erased {} pages", n);
+                summary.insert("pages_erased".to_string(), json!(n));
+            }
+        },
+        Commands::ReadoutProtect => {
+            info!("Enabling readout protection");
+
+            p.readout_protect()
+                .context("Error enabling readout protection")?;
+        },
+        Commands::ReadoutUnprotect => {
+            info!("Disabling readout protection (this will erase the device flash)");
+
+            p.readout_unprotect()
+                .context("Error disabling readout protection")?;
+        },
+        Commands::Unbrick => {
+            info!("Recovering device (this will erase the device flash)");
+
+            let blank = p.unbrick()
+                .context("Error recovering device")?;
+
+            match blank {
+                true => info!("Device recovered, flash blank"),
+                false => info!("Device recovered, flash not checked"),
+            }
+
+            summary.insert("blank".to_string(), json!(blank));
+        },
+        Commands::BlankCheck{offset, length} => {
+            info!("Checking {} bytes from offset 0x{:08x} are blank", length, offset);
+
+            let non_blank = p.find_non_blank(*offset, *length as usize)
+                .context("Error reading memory")?;
+
+            match non_blank {
+                Some(a) => info!("Memory not blank at 0x{:08x}", a),
+                None => info!("Memory blank"),
+            }
+
+            summary.insert("blank".to_string(), json!(non_blank.is_none()));
+            if let Some(a) = non_blank {
+                summary.insert("address".to_string(), json!(a));
+            }
+        },
+        Commands::Info => {
+            let info = p.get()
+                .context("Error fetching bootloader info")?;
+
+            info!("Bootloader version: {}.{} (0x{:02x})", info.version >> 4, info.version & 0x0F, info.version);
+            info!("Supported commands:");
+
+            for c in &info.commands {
+                info!("  {} (0x{:02x})", c, *c as u8);
+            }
+
+            summary.insert("version".to_string(), json!(info.version));
+            summary.insert("commands".to_string(), json!(info.commands.iter().map(|c| c.to_string()).collect::<Vec<_>>()));
+        },
+        Commands::ChipId{uid} => {
+            let id = p.chip_id()
+                .context("Error fetching chip ID")?;
+
+            info!("Chip ID: 0x{:04x} ({})", id, product_name(id).unwrap_or("unknown"));
+
+            summary.insert("chip_id".to_string(), json!(id));
+            summary.insert("product".to_string(), json!(product_name(id)));
+
+            if *uid {
+                let uid = p.unique_id(id)
+                    .context("Error reading unique ID")?;
+
+                info!("Unique ID: {}", hex::encode(uid));
+
+                summary.insert("uid".to_string(), json!(hex::encode(uid)));
+            }
+        },
+        Commands::Probe => {
+            let version = p.bootloader_version().unwrap_or_default();
+
+            info!("Bootloader present (version 0x{:02x})", version);
+
+            summary.insert("version".to_string(), json!(version));
+        },
+        Commands::Reset{bootloader} => {
+            if o.options.no_reset {
+                warn!("Reset disabled by --no-reset");
+            }
+
+            info!("Resetting device to {}", if *bootloader { "bootloader" } else { "application" });
+
+            p.reset(*bootloader)
+                .context("Error resetting device")?;
+        },
+        Commands::Go{offset, expect_ack, ..} => {
+            info!("Jumping to application at 0x{:08x}", offset);
+
+            p.go_with_ack(*offset, *expect_ack)
+                .context("Error executing go command")?;
+
+            info!("Jumped to application");
         }
     }
 
-    if !o.options.no_reset {
+    summary.insert("bytes".to_string(), json!(bytes));
+    summary.insert("elapsed_ms".to_string(), json!(start.elapsed().as_millis() as u64));
+
+    // Report timing for transfer and erase operations
+    if matches!(o.command, Commands::Read{..} | Commands::Dump{..} | Commands::Write{..} | Commands::Verify{..} | Commands::Compare{..} | Commands::Erase{..} | Commands::EraseAll) {
+        let elapsed = start.elapsed();
+
+        match bytes {
+            0 => info!("Completed in {:.2?}", elapsed),
+            _ => info!("Completed {} bytes in {:.2?} ({:.0} bytes/sec)", bytes, elapsed, bytes as f64 / elapsed.as_secs_f64()),
+        }
+    }
+
+    // Skip reset following go as this would restart the application,
+    // and following an explicit reset
+    let skip = matches!(o.command, Commands::Go{..} | Commands::Reset{..});
+
+    if !o.options.no_reset && !skip {
         debug!("Resetting device to application");
-        p.reset(false)
+        p.reset_into_app()
             .context("Error resetting device")?;
     }
 
-    Ok(())
+    Ok(summary)
 }
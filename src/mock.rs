@@ -0,0 +1,161 @@
+//! Mock serial port and delay implementations for testing protocol framing
+//! without attached hardware.
+
+use std::collections::VecDeque;
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::serial::{Read, Write};
+
+use crate::SerialPort;
+
+/// Mock serial port error
+#[derive(Clone, PartialEq, Debug)]
+pub enum MockError {
+    /// Written byte did not match the next expected byte
+    UnexpectedWrite { expected: u8, actual: u8 },
+    /// Error injected via [`MockSerial::fail_writes`]
+    Injected,
+}
+
+/// Mock serial port backed by scripted expected writes and queued reads
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct MockSerial {
+    /// Bytes expected to be written, checked in order if not empty
+    pub expected: VecDeque<u8>,
+
+    /// Bytes written to the port
+    pub written: Vec<u8>,
+
+    /// Bytes to be returned from reads
+    pub rx: VecDeque<u8>,
+
+    /// Bytes to be queued for reads once the provided number of bytes have been written
+    pub pending: VecDeque<(usize, Vec<u8>)>,
+
+    /// Recorded RTS levels
+    pub rts: Vec<bool>,
+
+    /// Recorded DTR levels
+    pub dtr: Vec<bool>,
+
+    /// Return an error on write
+    pub fail_writes: bool,
+}
+
+impl MockSerial {
+    /// Create a new empty mock serial port
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append bytes to the expected write script
+    pub fn expect(&mut self, data: &[u8]) -> &mut Self {
+        self.expected.extend(data);
+        self
+    }
+
+    /// Queue bytes to be returned from reads
+    pub fn queue(&mut self, data: &[u8]) -> &mut Self {
+        self.rx.extend(data);
+        self
+    }
+
+    /// Queue bytes to be returned from reads once `written` bytes have been written,
+    /// allowing responses to be sequenced with the host writes
+    pub fn queue_after(&mut self, written: usize, data: &[u8]) -> &mut Self {
+        self.pending.push_back((written, data.to_vec()));
+        self.release();
+        self
+    }
+
+    /// Check all expected writes have occurred and all queued reads consumed
+    pub fn done(&self) -> bool {
+        self.expected.is_empty() && self.rx.is_empty() && self.pending.is_empty()
+    }
+
+    /// Move pending responses to the read queue once enough bytes have been written
+    fn release(&mut self) {
+        while let Some((n, _)) = self.pending.front() {
+            if *n > self.written.len() {
+                break;
+            }
+
+            if let Some((_, d)) = self.pending.pop_front() {
+                self.rx.extend(d);
+            }
+        }
+    }
+}
+
+impl Write<u8> for MockSerial {
+    type Error = MockError;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        if self.fail_writes {
+            return Err(nb::Error::Other(MockError::Injected));
+        }
+
+        if let Some(expected) = self.expected.pop_front() {
+            if expected != word {
+                return Err(nb::Error::Other(MockError::UnexpectedWrite { expected, actual: word }));
+            }
+        }
+
+        self.written.push(word);
+        self.release();
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        if self.fail_writes {
+            return Err(nb::Error::Other(MockError::Injected));
+        }
+
+        Ok(())
+    }
+}
+
+impl Read<u8> for MockSerial {
+    type Error = MockError;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        match self.rx.pop_front() {
+            Some(v) => Ok(v),
+            None => Err(nb::Error::WouldBlock),
+        }
+    }
+}
+
+impl SerialPort<MockError> for MockSerial {
+    fn set_rts(&mut self, level: bool) -> Result<(), MockError> {
+        self.rts.push(level);
+        Ok(())
+    }
+    fn set_dtr(&mut self, level: bool) -> Result<(), MockError> {
+        self.dtr.push(level);
+        Ok(())
+    }
+}
+
+/// Mock delay, recording total elapsed time without sleeping
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct MockDelay {
+    /// Total requested delay in milliseconds
+    pub elapsed_ms: u64,
+
+    /// Total requested delay in microseconds
+    pub elapsed_us: u64,
+}
+
+impl DelayMs<u32> for MockDelay {
+    fn delay_ms(&mut self, ms: u32) {
+        self.elapsed_ms += ms as u64;
+    }
+}
+
+impl DelayUs<u32> for MockDelay {
+    fn delay_us(&mut self, us: u32) {
+        self.elapsed_us += us as u64;
+    }
+}
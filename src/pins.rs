@@ -0,0 +1,92 @@
+//! Serial port adapter using GPIO pins for reset control.
+//!
+//! Wraps any embedded-hal serial port with a pair of output pins driving the
+//! target reset (RTS) and boot (DTR) lines, allowing the loader to run on an MCU
+//! acting as a programming jig.
+
+use embedded_hal::digital::v2::OutputPin;
+use embedded_hal::serial::{Read, Write};
+
+use crate::SerialPort;
+
+/// Pin controlled serial port error
+#[derive(Clone, PartialEq, Debug)]
+pub enum PinPortError<S, P> {
+    /// Underlying serial port error
+    Serial(S),
+    /// RTS or DTR pin error
+    Pin(P),
+}
+
+/// Serial port with RTS / DTR driven by the provided output pins
+///
+/// RTS / DTR levels map directly to the pin outputs (`true` driving the pin high),
+/// use the `rts_invert` and `dtr_invert` options to match the jig wiring.
+pub struct PinControlledPort<U, RTS, DTR> {
+    uart: U,
+    rts: RTS,
+    dtr: DTR,
+}
+
+impl<U, RTS, DTR> PinControlledPort<U, RTS, DTR> {
+    /// Create a new pin controlled serial port
+    pub fn new(uart: U, rts: RTS, dtr: DTR) -> Self {
+        Self { uart, rts, dtr }
+    }
+
+    /// Release the underlying serial port and pins
+    pub fn free(self) -> (U, RTS, DTR) {
+        (self.uart, self.rts, self.dtr)
+    }
+}
+
+fn set_pin<P: OutputPin>(pin: &mut P, level: bool) -> Result<(), P::Error> {
+    match level {
+        true => pin.set_high(),
+        false => pin.set_low(),
+    }
+}
+
+impl<U, RTS, DTR, S, P> Read<u8> for PinControlledPort<U, RTS, DTR>
+where
+    U: Read<u8, Error = S> + Write<u8, Error = S>,
+    RTS: OutputPin<Error = P>,
+    DTR: OutputPin<Error = P>,
+{
+    type Error = PinPortError<S, P>;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        self.uart.read().map_err(|e| e.map(PinPortError::Serial))
+    }
+}
+
+impl<U, RTS, DTR, S, P> Write<u8> for PinControlledPort<U, RTS, DTR>
+where
+    U: Read<u8, Error = S> + Write<u8, Error = S>,
+    RTS: OutputPin<Error = P>,
+    DTR: OutputPin<Error = P>,
+{
+    type Error = PinPortError<S, P>;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        self.uart.write(word).map_err(|e| e.map(PinPortError::Serial))
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.uart.flush().map_err(|e| e.map(PinPortError::Serial))
+    }
+}
+
+impl<U, RTS, DTR, S, P> SerialPort<PinPortError<S, P>> for PinControlledPort<U, RTS, DTR>
+where
+    U: Read<u8, Error = S> + Write<u8, Error = S>,
+    RTS: OutputPin<Error = P>,
+    DTR: OutputPin<Error = P>,
+{
+    fn set_rts(&mut self, level: bool) -> Result<(), PinPortError<S, P>> {
+        set_pin(&mut self.rts, level).map_err(PinPortError::Pin)
+    }
+    fn set_dtr(&mut self, level: bool) -> Result<(), PinPortError<S, P>> {
+        set_pin(&mut self.dtr, level).map_err(PinPortError::Pin)
+    }
+}
@@ -1,4 +1,6 @@
-
+use core::convert::TryFrom;
+use core::fmt;
+use core::iter::once;
 
 pub const UART_DISC: u8 = 0x7F;
 
@@ -7,7 +9,13 @@ pub const UART_NACK: u8 = 0x1F;
 
 pub const MAX_CHUNK: usize = 256;
 
-#[derive(Debug, PartialEq, Clone)]
+/// Maximum number of pages in a standard erase, as N-1 = 0xFF denotes a mass erase
+pub const MAX_ERASE_PAGES: usize = 255;
+
+/// Maximum number of pages in an extended erase, as N-1 from 0xFFF0 denotes a special erase
+pub const MAX_EXTENDED_ERASE_PAGES: usize = 0xFFF0;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Command {
     /// Fetch bootloader version and allowed commands
     Get = 0x00,
@@ -45,3 +53,300 @@ pub enum Command {
     /// Disables the read protection.
     ReadoutUnprotect = 0x92,
 }
+
+/// Note the error is the raw byte rather than [`Error::InvalidResponse`](crate::Error::InvalidResponse),
+/// as the serial error type of [`Error`](enum@crate::Error) cannot be determined here. Callers treating an
+/// unknown command as a failure map this with `.map_err(|_| Error::InvalidResponse)`.
+impl TryFrom<u8> for Command {
+    type Error = u8;
+
+    /// Convert a raw command byte to a [`Command`], returning the unrecognised byte on failure
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        let c = match v {
+            0x00 => Command::Get,
+            0x01 => Command::GetVersionReadStatus,
+            0x02 => Command::GetId,
+            0x11 => Command::ReadMemory,
+            0x21 => Command::Go,
+            0x31 => Command::WriteMemory,
+            0x43 => Command::Erase,
+            0x44 => Command::ExtendedErase,
+            0x63 => Command::WriteProtect,
+            0x73 => Command::WriteUnprotect,
+            0x82 => Command::ReadoutProtect,
+            0x92 => Command::ReadoutUnprotect,
+            _ => return Err(v),
+        };
+
+        Ok(c)
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Command::Get => "Get",
+            Command::GetVersionReadStatus => "GetVersionReadStatus",
+            Command::GetId => "GetId",
+            Command::ReadMemory => "ReadMemory",
+            Command::Go => "Go",
+            Command::WriteMemory => "WriteMemory",
+            Command::Erase => "Erase",
+            Command::ExtendedErase => "ExtendedErase",
+            Command::WriteProtect => "WriteProtect",
+            Command::WriteUnprotect => "WriteUnprotect",
+            Command::ReadoutProtect => "ReadoutProtect",
+            Command::ReadoutUnprotect => "ReadoutUnprotect",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// Bootloader information returned by the Get command
+#[cfg(feature = "std")]
+#[derive(Debug, PartialEq, Clone)]
+pub struct BootloaderInfo {
+    /// Bootloader version
+    pub version: u8,
+
+    /// Commands supported by the bootloader
+    pub commands: Vec<Command>,
+}
+
+/// Set of commands supported by the bootloader
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct CommandSet([u32; 8]);
+
+impl CommandSet {
+    /// Add a command to the set
+    pub fn insert(&mut self, command: Command) {
+        let c = command as u8;
+        self.0[(c >> 5) as usize] |= 1 << (c & 0x1F);
+    }
+
+    /// Check whether the set contains the provided command
+    pub fn contains(&self, command: Command) -> bool {
+        let c = command as u8;
+        self.0[(c >> 5) as usize] & (1 << (c & 0x1F)) != 0
+    }
+}
+
+/// Special erase kinds for the ExtendedErase command
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExtendedEraseKind {
+    /// Erase the entire flash memory
+    Mass,
+
+    /// Erase flash memory bank 1
+    Bank1,
+
+    /// Erase flash memory bank 2
+    Bank2,
+}
+
+impl ExtendedEraseKind {
+    /// Fetch the two byte special erase code
+    pub fn code(&self) -> u16 {
+        match self {
+            ExtendedEraseKind::Mass => 0xFFFF,
+            ExtendedEraseKind::Bank1 => 0xFFFE,
+            ExtendedEraseKind::Bank2 => 0xFFFD,
+        }
+    }
+}
+
+/// Ack decoding error
+#[derive(Debug, PartialEq, Clone)]
+pub enum AckError {
+    /// Bootloader responded with a NACK
+    Nack,
+
+    /// Bootloader responded with an unexpected byte
+    Invalid(u8),
+}
+
+/// Interpret a bootloader ack byte
+pub fn ack_from_byte(b: u8) -> Result<(), AckError> {
+    match b {
+        UART_ACK => Ok(()),
+        UART_NACK => Err(AckError::Nack),
+        _ => Err(AckError::Invalid(b)),
+    }
+}
+
+/// Compute the xor checksum over the provided data
+pub fn xor_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0x00, |a, b| a ^ b)
+}
+
+/// Encode a command with its complement
+pub fn encode_command(command: &Command) -> [u8; 2] {
+    encode_raw_command(*command as u8)
+}
+
+/// Encode a raw command byte with its complement
+pub fn encode_raw_command(c: u8) -> [u8; 2] {
+    [c, !c]
+}
+
+/// Encode a big-endian address with xor checksum
+pub fn encode_address(addr: u32) -> [u8; 5] {
+    let a = addr.to_be_bytes();
+    [a[0], a[1], a[2], a[3], xor_checksum(&a)]
+}
+
+/// Encode a read length (N-1) with its complement
+///
+/// `len` must be between 1 and [`MAX_CHUNK`] bytes
+pub fn encode_read_length(len: usize) -> [u8; 2] {
+    let n = (len - 1) as u8;
+    [n, !n]
+}
+
+/// Encode write data as length (N-1), data, and xor checksum over both
+///
+/// `data` must be between 1 and [`MAX_CHUNK`] bytes
+pub fn encode_write_data(data: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    let n = (data.len() - 1) as u8;
+    let csum = n ^ xor_checksum(data);
+
+    once(n).chain(data.iter().copied()).chain(once(csum))
+}
+
+/// Encode an erase page list as count (N-1), page numbers, and xor checksum over both
+///
+/// Returns `None` for an empty list or more than [`MAX_ERASE_PAGES`] pages,
+/// as 256 pages (N-1 = 0xFF) would instead request a mass erase.
+pub fn encode_erase(pages: &[u8]) -> Option<impl Iterator<Item = u8> + '_> {
+    if pages.is_empty() || pages.len() > MAX_ERASE_PAGES {
+        return None;
+    }
+
+    let n = (pages.len() - 1) as u8;
+    let csum = n ^ xor_checksum(pages);
+
+    Some(once(n).chain(pages.iter().copied()).chain(once(csum)))
+}
+
+/// Encode an extended erase page list as two byte count (N-1), two byte page numbers,
+/// and xor checksum over all
+///
+/// Returns `None` for an empty list or more than [`MAX_EXTENDED_ERASE_PAGES`] pages,
+/// as counts from 0xFFF0 are reserved for special erase codes.
+pub fn encode_extended_erase(pages: &[u16]) -> Option<impl Iterator<Item = u8> + '_> {
+    if pages.is_empty() || pages.len() > MAX_EXTENDED_ERASE_PAGES {
+        return None;
+    }
+
+    let n = (pages.len() - 1) as u16;
+    let words = once(n).chain(pages.iter().copied());
+    let csum = words.clone().flat_map(u16::to_be_bytes).fold(0, |a, b| a ^ b);
+
+    Some(words.flat_map(u16::to_be_bytes).chain(once(csum)))
+}
+
+/// CRC32 polynomial used by the STM32 hardware CRC unit
+pub const CRC32_POLY: u32 = 0x04C1_1DB7;
+
+/// CRC32 initial value used by the STM32 hardware CRC unit
+pub const CRC32_INIT: u32 = 0xFFFF_FFFF;
+
+/// Update a CRC32 matching the STM32 hardware CRC unit with the provided data
+///
+/// Data is processed as little-endian 32-bit words (without reflection or final xor),
+/// with any trailing partial word padded with 0xFF to match erased flash.
+pub fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for w in data.chunks(4) {
+        let mut b = [0xFFu8; 4];
+        b[..w.len()].copy_from_slice(w);
+
+        crc ^= u32::from_le_bytes(b);
+
+        for _ in 0..32 {
+            crc = match crc & 0x8000_0000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ CRC32_POLY,
+            };
+        }
+    }
+
+    crc
+}
+
+/// Compute a CRC32 matching the STM32 hardware CRC unit over the provided data
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(CRC32_INIT, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_from_byte() {
+        for c in [Command::Get, Command::ReadMemory, Command::ExtendedErase, Command::ReadoutUnprotect] {
+            assert_eq!(Command::try_from(c as u8), Ok(c));
+        }
+
+        assert_eq!(Command::try_from(0x55), Err(0x55));
+    }
+
+    #[test]
+    fn crc32_reference() {
+        // STM32 CRC unit reference vector, with the word written as a little endian u32
+        assert_eq!(crc32(&0x1234_5678u32.to_le_bytes()), 0xDF8A_8A2B);
+
+        // Updates over whole words match a single computation
+        let data = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        assert_eq!(crc32_update(crc32(&data[..4]), &data[4..]), crc32(&data));
+    }
+
+    #[test]
+    fn crc32_partial_word() {
+        // Trailing partial words are padded with 0xFF, as read from erased flash
+        assert_eq!(crc32(&[0x78, 0x56]), crc32(&[0x78, 0x56, 0xFF, 0xFF]));
+        assert_eq!(crc32(&[0x01, 0x02, 0x03, 0x04, 0x05]), crc32(&[0x01, 0x02, 0x03, 0x04, 0x05, 0xFF, 0xFF, 0xFF]));
+        assert_ne!(crc32(&[0x78, 0x56]), crc32(&[0x78, 0x56, 0x00, 0x00]));
+    }
+
+    #[test]
+    fn address_checksum() {
+        assert_eq!(xor_checksum(&[0x08, 0x00, 0x00, 0x00]), 0x08);
+        assert_eq!(xor_checksum(&[]), 0x00);
+
+        assert_eq!(encode_address(0x0800_0000), [0x08, 0x00, 0x00, 0x00, 0x08]);
+        assert_eq!(encode_address(0x2000_1234), [0x20, 0x00, 0x12, 0x34, 0x06]);
+    }
+
+    #[test]
+    fn erase_encoding() {
+        let frame: Vec<u8> = encode_erase(&[0x01, 0x02, 0x03]).unwrap().collect();
+        assert_eq!(frame, &[0x02, 0x01, 0x02, 0x03, 0x02]);
+
+        let frame: Vec<u8> = encode_erase(&[0x00]).unwrap().collect();
+        assert_eq!(frame, &[0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn erase_encoding_limits() {
+        assert!(encode_erase(&[]).is_none());
+        assert!(encode_erase(&[0u8; MAX_ERASE_PAGES + 1]).is_none());
+        assert!(encode_erase(&[0u8; MAX_ERASE_PAGES]).is_some());
+    }
+
+    #[test]
+    fn extended_erase_encoding() {
+        let frame: Vec<u8> = encode_extended_erase(&[0x0001, 0x0102]).unwrap().collect();
+        assert_eq!(frame, &[0x00, 0x01, 0x00, 0x01, 0x01, 0x02, 0x03]);
+
+        let frame: Vec<u8> = encode_extended_erase(&[0x0000]).unwrap().collect();
+        assert_eq!(frame, &[0x00, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn extended_erase_encoding_limits() {
+        assert!(encode_extended_erase(&[]).is_none());
+        assert!(encode_extended_erase(&vec![0u16; MAX_EXTENDED_ERASE_PAGES + 1]).is_none());
+    }
+}
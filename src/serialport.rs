@@ -0,0 +1,113 @@
+use std::io::{ErrorKind as IoErrorKind, Read as _, Write as _};
+use std::time::Duration;
+
+use ::serialport::{DataBits, FlowControl, Parity, StopBits};
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::serial::{Read, Write};
+
+use crate::{Error, Options, Programmer, SerialPort};
+
+/// Cross-platform serial port wrapper using the `serialport` crate
+pub struct NativeSerial(pub Box<dyn ::serialport::SerialPort>);
+
+/// Delay implementation using `std::thread::sleep`
+pub struct StdDelay;
+
+impl DelayMs<u32> for StdDelay {
+    fn delay_ms(&mut self, ms: u32) {
+        std::thread::sleep(Duration::from_millis(ms as u64));
+    }
+}
+
+impl DelayUs<u32> for StdDelay {
+    fn delay_us(&mut self, us: u32) {
+        std::thread::sleep(Duration::from_micros(us as u64));
+    }
+}
+
+fn kind(e: ::serialport::Error) -> IoErrorKind {
+    std::io::Error::from(e).kind()
+}
+
+impl Read<u8> for NativeSerial {
+    type Error = IoErrorKind;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        // Only read when data is available to avoid blocking
+        let n = self.0.bytes_to_read().map_err(|e| nb::Error::Other(kind(e)))?;
+        if n == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        let mut b = [0u8; 1];
+        match self.0.read(&mut b) {
+            Ok(1) => Ok(b[0]),
+            Ok(_) => Err(nb::Error::WouldBlock),
+            Err(e) if e.kind() == IoErrorKind::TimedOut => Err(nb::Error::WouldBlock),
+            Err(e) => Err(nb::Error::Other(e.kind())),
+        }
+    }
+}
+
+impl Write<u8> for NativeSerial {
+    type Error = IoErrorKind;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        self.0.write_all(&[word]).map_err(|e| nb::Error::Other(e.kind()))
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.0.flush().map_err(|e| nb::Error::Other(e.kind()))
+    }
+}
+
+impl SerialPort<IoErrorKind> for NativeSerial {
+    fn set_rts(&mut self, level: bool) -> Result<(), IoErrorKind> {
+        self.0.write_request_to_send(level).map_err(kind)
+    }
+    fn set_dtr(&mut self, level: bool) -> Result<(), IoErrorKind> {
+        self.0.write_data_terminal_ready(level).map_err(kind)
+    }
+}
+
+impl Programmer<NativeSerial, StdDelay, IoErrorKind> {
+    /// Create a new cross-platform serial port programmer instance
+    pub fn serialport(
+        port: &str,
+        baud: u32,
+        options: Options,
+    ) -> Result<Self, anyhow::Error> {
+        let (data_bits, parity, stop_bits) = serial_config(&options)?;
+
+        // Open port and apply settings
+        let port = ::serialport::new(port, baud)
+            .data_bits(data_bits)
+            .stop_bits(stop_bits)
+            .parity(parity)
+            .flow_control(FlowControl::None)
+            .timeout(Duration::from_millis(options.response_timeout_ms as u64))
+            .open()?;
+
+        // Return instance
+        let s = Self::new(NativeSerial(port), StdDelay, options)?;
+
+        Ok(s)
+    }
+
+    /// Reconfigure the serial port baud rate, re-running the connection handshake
+    /// (including device reset) at the new rate
+    pub fn set_baud(&mut self, baud: usize) -> Result<(), Error<IoErrorKind>> {
+        self.port.0.set_baud_rate(baud as u32).map_err(kind)?;
+
+        self.reconnect()
+    }
+}
+
+/// Convert serial options to `serialport` settings
+fn serial_config(options: &Options) -> Result<(DataBits, Parity, StopBits), anyhow::Error> {
+    options.serial_config(
+        [DataBits::Five, DataBits::Six, DataBits::Seven, DataBits::Eight],
+        [Parity::None, Parity::Odd, Parity::Even],
+        [StopBits::One, StopBits::Two],
+    )
+}
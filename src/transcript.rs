@@ -0,0 +1,69 @@
+//! Trace level transaction logging.
+//!
+//! Collects the bytes sent and received for each exchange with the bootloader
+//! so these can be logged as a single aligned line on completion.
+
+use std::fmt;
+use std::time::Instant;
+
+use log::{log_enabled, trace, Level};
+
+/// Bytes transmitted and received since the last completed exchange
+#[derive(Clone, PartialEq, Debug, Default)]
+pub(crate) struct Transcript {
+    tx: Vec<u8>,
+    rx: Vec<u8>,
+    start: Option<Instant>,
+}
+
+impl Transcript {
+    /// Record a transmitted byte
+    pub fn tx(&mut self, b: u8) {
+        if !log_enabled!(Level::Trace) {
+            return;
+        }
+
+        self.start.get_or_insert_with(Instant::now);
+        self.tx.push(b);
+    }
+
+    /// Record a received byte
+    pub fn rx(&mut self, b: u8) {
+        if !log_enabled!(Level::Trace) {
+            return;
+        }
+
+        self.start.get_or_insert_with(Instant::now);
+        self.rx.push(b);
+    }
+
+    /// Log and clear the current exchange on receipt of an ack (or nack)
+    pub fn complete(&mut self, label: impl fmt::Display) {
+        if !log_enabled!(Level::Trace) {
+            return;
+        }
+
+        let elapsed = self.start.take().map(|s| s.elapsed().as_millis()).unwrap_or(0);
+
+        trace!("{:<20} TX: {} | RX: {} | ack in {}ms", label.to_string(), Hex(&self.tx), Hex(&self.rx), elapsed);
+
+        self.tx.clear();
+        self.rx.clear();
+    }
+}
+
+/// Space separated hex formatting for byte slices
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+
+        Ok(())
+    }
+}